@@ -0,0 +1,558 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::diagnostic::token_diagnostic;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Token;
+use crate::word::DataType;
+use crate::word::Word;
+
+// number of values a builtin word pops and pushes
+fn stack_effect(word: &Word) -> (usize, usize) {
+    match word {
+        Word::OpPush(_) | Word::OpKey | Word::OpMem | Word::OpArgc | Word::OpArgv | Word::OpEnvp => (0, 1),
+        Word::OpPushStr(_) => (0, 2),
+        Word::OpDump | Word::OpDrop | Word::OpEmit | Word::OpIf(_) | Word::OpDo(_) | Word::OpExit => (1, 0),
+        Word::OpZeroEqual
+        | Word::OpZeroLt
+        | Word::OpZeroGt
+        | Word::OpNegate
+        | Word::OpAbs
+        | Word::OpNot
+        | Word::OpLoad(_)
+        | Word::OpCast(_) => (1, 1),
+        Word::OpDup => (1, 2),
+        Word::OpPlus
+        | Word::OpMinus
+        | Word::OpEqual
+        | Word::OpGt
+        | Word::OpLt
+        | Word::OpLe
+        | Word::OpGe
+        | Word::OpNe
+        | Word::OpMul
+        | Word::OpDiv
+        | Word::OpMod
+        | Word::OpMin
+        | Word::OpMax
+        | Word::OpAnd
+        | Word::OpOr
+        | Word::OpXor
+        | Word::OpShl
+        | Word::OpShr
+        | Word::OpSar
+        | Word::OpNip
+        | Word::OpAccept => (2, 1),
+        Word::OpPuts | Word::OpStore(_) | Word::OpTwoDrop => (2, 0),
+        Word::OpDivMod | Word::OpSwap => (2, 2),
+        Word::OpOver | Word::OpTuck => (2, 3),
+        Word::OpTwoDup => (2, 4),
+        Word::OpRot | Word::OpMinusRot => (3, 3),
+        Word::OpTwoSwap => (4, 4),
+        Word::OpSyscall(arg_count) => (arg_count + 1, 1),
+        Word::OpElse(_)
+        | Word::OpEnd(_)
+        | Word::OpWhile
+        | Word::OpConst(_)
+        | Word::OpMacro(_)
+        | Word::OpDefine(_, _)
+        | Word::OpCall(_, _)
+        | Word::OpReturn => (0, 0),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct StackEffect {
+    inputs: usize,
+    outputs: usize,
+}
+
+struct Block {
+    token_idx: usize,
+    // depth when the block was entered and, for 'else', where the 'if' branch left it
+    start: Option<i64>,
+    branch: Option<i64>,
+}
+
+// blocks are balanced after cross-referencing, this only guards against bugs
+fn pop_block<T>(blocks: &mut Vec<T>, token_idx: usize) -> Result<T, (usize, String)> {
+    blocks.pop().ok_or_else(|| (token_idx, "block stack is empty".to_owned()))
+}
+
+// Abstractly runs the tokens in `range`, tracking only the stack depth relative
+// to its start. A depth of None means the code is unreachable, e.g. after
+// 'exit' or a call to a word whose effect is not known (yet). At top level
+// going below the start is an underflow, inside a definition it means the
+// word takes inputs. Returns the effect of the whole range, None if it never
+// finishes, or the index of the offending token with a message.
+fn check_stack_range(
+    program: &[Token],
+    range: Range<usize>,
+    effects: &HashMap<usize, Option<StackEffect>>,
+    top_level: bool,
+) -> Result<Option<StackEffect>, (usize, String)> {
+    let mut depth: Option<i64> = Some(0);
+    let mut lowest: i64 = 0;
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut token_idx = range.start;
+    while token_idx < range.end {
+        let token = &program[token_idx];
+        let (inputs, outputs) = match &token.word {
+            Word::OpDefine(_, Some(after_end_idx)) if top_level => {
+                token_idx = *after_end_idx;
+                continue;
+            }
+            Word::OpCall(_, Some(define_idx)) => match effects.get(define_idx).copied().flatten() {
+                Some(effect) => (effect.inputs, effect.outputs),
+                None => {
+                    depth = None;
+                    (0, 0)
+                }
+            },
+            word => stack_effect(word),
+        };
+        if let Some(before) = depth {
+            let after = before - inputs as i64;
+            if top_level && after < 0 {
+                let values = if inputs == 1 { "value" } else { "values" };
+                let msg = format!("stack underflow: needs {inputs} {values} but the stack has {before}");
+                return Err((token_idx, msg));
+            }
+            lowest = lowest.min(after);
+            depth = Some(after + outputs as i64);
+        }
+        match &token.word {
+            Word::OpIf(_) | Word::OpWhile | Word::OpDo(_) => blocks.push(Block {
+                token_idx,
+                start: depth,
+                branch: None,
+            }),
+            Word::OpElse(_) => {
+                let if_block = pop_block(&mut blocks, token_idx)?;
+                blocks.push(Block {
+                    token_idx,
+                    start: if_block.start,
+                    branch: depth,
+                });
+                depth = if_block.start;
+            }
+            Word::OpEnd(_) => {
+                let block = pop_block(&mut blocks, token_idx)?;
+                match program[block.token_idx].word {
+                    Word::OpIf(_) => {
+                        if let (Some(start), Some(end)) = (block.start, depth) {
+                            if start != end {
+                                let msg = format!(
+                                    "'if' without 'else' must leave the stack depth unchanged, but changes it by {:+}",
+                                    end - start
+                                );
+                                return Err((token_idx, msg));
+                            }
+                        }
+                        depth = block.start;
+                    }
+                    Word::OpElse(_) => {
+                        if let (Some(start), Some(then), Some(elze)) = (block.start, block.branch, depth) {
+                            if then != elze {
+                                let msg = format!(
+                                    "'if' and 'else' branches leave different stack depths ({:+} and {:+})",
+                                    then - start,
+                                    elze - start
+                                );
+                                return Err((token_idx, msg));
+                            }
+                        }
+                        depth = depth.or(block.branch);
+                    }
+                    Word::OpDo(_) => {
+                        let wile_block = pop_block(&mut blocks, token_idx)?;
+                        if let (Some(start), Some(end)) = (wile_block.start, depth) {
+                            if start != end {
+                                let msg = format!(
+                                    "'while' loop must leave the stack depth unchanged, but changes it by {:+} per iteration",
+                                    end - start
+                                );
+                                return Err((token_idx, msg));
+                            }
+                        }
+                        depth = block.start;
+                    }
+                    _ => (),
+                }
+            }
+            Word::OpExit => depth = None,
+            _ => (),
+        }
+        token_idx += 1;
+    }
+    Ok(depth.map(|depth| StackEffect {
+        inputs: -lowest as usize,
+        outputs: (depth - lowest) as usize,
+    }))
+}
+
+// Checks that no word can underflow the stack and that every 'if'/'else' and
+// 'while' leaves the stack balanced, before anything runs. Effects of defined
+// words are inferred first, iterating so that recursive words settle on the
+// effect of their non recursive branches.
+pub(crate) fn check_stack_effects(program: &[Token]) -> Result<HashMap<usize, Option<StackEffect>>, Diagnostic> {
+    let definitions: Vec<(usize, usize)> = program
+        .iter()
+        .enumerate()
+        .filter_map(|(token_idx, token)| match token.word {
+            Word::OpDefine(_, Some(after_end_idx)) => Some((token_idx, after_end_idx)),
+            _ => None,
+        })
+        .collect();
+    let mut effects: HashMap<usize, Option<StackEffect>> =
+        definitions.iter().map(|&(define_idx, _)| (define_idx, None)).collect();
+    for _ in 0..=definitions.len() {
+        let mut changed = false;
+        for &(define_idx, after_end_idx) in &definitions {
+            let body = define_idx + 1..after_end_idx - 1;
+            if let Ok(effect) = check_stack_range(program, body, &effects, false) {
+                if effects[&define_idx] != effect {
+                    effects.insert(define_idx, effect);
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for &(define_idx, after_end_idx) in &definitions {
+        let body = define_idx + 1..after_end_idx - 1;
+        match check_stack_range(program, body, &effects, false) {
+            Ok(effect) if effect == effects[&define_idx] => (),
+            Ok(_) => {
+                let msg = "recursive word has no consistent stack effect";
+                return Err(token_diagnostic(&program[define_idx], "stack", msg));
+            }
+            Err((token_idx, msg)) => return Err(token_diagnostic(&program[token_idx], "stack", &msg)),
+        }
+    }
+    if let Err((token_idx, msg)) = check_stack_range(program, 0..program.len(), &effects, true) {
+        return Err(token_diagnostic(&program[token_idx], "stack", &msg));
+    }
+    Ok(effects)
+}
+
+fn expect_type(found: DataType, expected: &[DataType]) -> Result<DataType, String> {
+    if found == DataType::Any || expected.contains(&found) {
+        return Ok(found);
+    }
+    let expected: Vec<String> = expected.iter().map(|data_type| data_type.to_string()).collect();
+    Err(format!("type mismatch: expected {} but found {found}", expected.join(" or ")))
+}
+
+// pops `inputs` values and pushes them back in the given order
+fn permute_types(stack: &mut Vec<DataType>, inputs: usize, order: &[usize]) {
+    let args = stack.split_off(stack.len() - inputs);
+    stack.extend(order.iter().map(|&arg_idx| args[arg_idx]));
+}
+
+// Applies a builtin word to the types on the stack. The stack effect check has
+// already run, so the stack always holds enough values.
+fn apply_types(word: &Word, stack: &mut Vec<DataType>) -> Result<(), String> {
+    use DataType::{Any, Bool, Int, Ptr};
+    match word {
+        Word::OpPush(_) | Word::OpKey | Word::OpArgc => stack.push(Int),
+        Word::OpMem | Word::OpArgv | Word::OpEnvp => stack.push(Ptr),
+        Word::OpPushStr(_) => stack.extend([Int, Ptr]),
+        Word::OpPlus => {
+            let b = stack.pop().unwrap_or(Any);
+            let a = stack.pop().unwrap_or(Any);
+            stack.push(match (a, b) {
+                (Int, Int) => Int,
+                (Ptr, Int | Any) | (Int | Any, Ptr) => Ptr,
+                (Int | Any, Int | Any) => Any,
+                _ => return Err(format!("type mismatch: cannot add {a} and {b}")),
+            });
+        }
+        Word::OpMinus => {
+            let b = stack.pop().unwrap_or(Any);
+            let a = stack.pop().unwrap_or(Any);
+            stack.push(match (a, b) {
+                (Int, Int) | (Ptr | Any, Ptr) => Int,
+                (Ptr, Int) => Ptr,
+                (Int | Any, Int | Any) | (Ptr, Any) => Any,
+                _ => return Err(format!("type mismatch: cannot subtract {b} from {a}")),
+            });
+        }
+        Word::OpMul
+        | Word::OpDiv
+        | Word::OpMod
+        | Word::OpDivMod
+        | Word::OpMin
+        | Word::OpMax
+        | Word::OpShl
+        | Word::OpShr
+        | Word::OpSar => {
+            expect_type(stack.pop().unwrap_or(Any), &[Int])?;
+            expect_type(stack.pop().unwrap_or(Any), &[Int])?;
+            stack.push(Int);
+            if *word == Word::OpDivMod {
+                stack.push(Int);
+            }
+        }
+        Word::OpNegate | Word::OpAbs => {
+            expect_type(stack.pop().unwrap_or(Any), &[Int])?;
+            stack.push(Int);
+        }
+        Word::OpAnd | Word::OpOr | Word::OpXor => {
+            let b = stack.pop().unwrap_or(Any);
+            let a = stack.pop().unwrap_or(Any);
+            stack.push(match (a, b) {
+                (Int | Bool, Any) => a,
+                (Any, Int | Bool | Any) => b,
+                (Int, Int) | (Bool, Bool) => a,
+                _ => return Err(format!("type mismatch: cannot combine {a} and {b} bitwise")),
+            });
+        }
+        Word::OpNot => {
+            // bitwise, it turns 0 and 1 into -1 and -2 which are both true
+            if stack.last() == Some(&Bool) {
+                return Err("type mismatch: 'not' is bitwise and cannot negate a bool, use '0=' instead".to_owned());
+            }
+            let a = expect_type(stack.pop().unwrap_or(Any), &[Int])?;
+            stack.push(a);
+        }
+        Word::OpEqual | Word::OpNe | Word::OpGt | Word::OpLt | Word::OpLe | Word::OpGe => {
+            let b = stack.pop().unwrap_or(Any);
+            let a = stack.pop().unwrap_or(Any);
+            let ordered = !matches!(word, Word::OpEqual | Word::OpNe);
+            if ordered {
+                expect_type(a, &[Int, Ptr])?;
+                expect_type(b, &[Int, Ptr])?;
+            }
+            if a != b && a != Any && b != Any {
+                return Err(format!("type mismatch: cannot compare {a} with {b}"));
+            }
+            stack.push(Bool);
+        }
+        Word::OpZeroEqual => {
+            stack.pop();
+            stack.push(Bool);
+        }
+        Word::OpZeroLt | Word::OpZeroGt => {
+            expect_type(stack.pop().unwrap_or(Any), &[Int])?;
+            stack.push(Bool);
+        }
+        Word::OpIf(_) | Word::OpDo(_) => {
+            expect_type(stack.pop().unwrap_or(Any), &[Bool])?;
+        }
+        Word::OpEmit | Word::OpExit => {
+            expect_type(stack.pop().unwrap_or(Any), &[Int])?;
+        }
+        Word::OpPuts => {
+            expect_type(stack.pop().unwrap_or(Any), &[Ptr])?;
+            expect_type(stack.pop().unwrap_or(Any), &[Int])?;
+        }
+        Word::OpAccept => {
+            expect_type(stack.pop().unwrap_or(Any), &[Int])?;
+            expect_type(stack.pop().unwrap_or(Any), &[Ptr])?;
+            stack.push(Int);
+        }
+        Word::OpLoad(_) => {
+            expect_type(stack.pop().unwrap_or(Any), &[Ptr])?;
+            stack.push(Int);
+        }
+        Word::OpStore(_) => {
+            expect_type(stack.pop().unwrap_or(Any), &[Ptr])?;
+            stack.pop();
+        }
+        Word::OpSyscall(arg_count) => {
+            expect_type(stack.pop().unwrap_or(Any), &[Int])?;
+            stack.truncate(stack.len().saturating_sub(*arg_count));
+            stack.push(Int);
+        }
+        Word::OpCast(data_type) => {
+            stack.pop();
+            stack.push(*data_type);
+        }
+        Word::OpDump | Word::OpDrop => permute_types(stack, 1, &[]),
+        Word::OpDup => permute_types(stack, 1, &[0, 0]),
+        Word::OpSwap => permute_types(stack, 2, &[1, 0]),
+        Word::OpOver => permute_types(stack, 2, &[0, 1, 0]),
+        Word::OpRot => permute_types(stack, 3, &[1, 2, 0]),
+        Word::OpMinusRot => permute_types(stack, 3, &[2, 0, 1]),
+        Word::OpNip => permute_types(stack, 2, &[1]),
+        Word::OpTuck => permute_types(stack, 2, &[1, 0, 1]),
+        Word::OpTwoDup => permute_types(stack, 2, &[0, 1, 0, 1]),
+        Word::OpTwoDrop => permute_types(stack, 2, &[]),
+        Word::OpTwoSwap => permute_types(stack, 4, &[2, 3, 0, 1]),
+        Word::OpElse(_)
+        | Word::OpEnd(_)
+        | Word::OpWhile
+        | Word::OpConst(_)
+        | Word::OpMacro(_)
+        | Word::OpDefine(_, _)
+        | Word::OpCall(_, _)
+        | Word::OpReturn => (),
+    }
+    Ok(())
+}
+
+fn merge_types(a: &[DataType], b: &[DataType]) -> Option<Vec<DataType>> {
+    a.iter()
+        .zip(b)
+        .map(|(&a, &b)| match (a, b) {
+            _ if a == b => Some(a),
+            (DataType::Any, _) | (_, DataType::Any) => Some(DataType::Any),
+            _ => None,
+        })
+        .collect()
+}
+
+fn format_types(stack: &[DataType]) -> String {
+    let types: Vec<String> = stack.iter().map(|data_type| data_type.to_string()).collect();
+    format!("[{}]", types.join(" "))
+}
+
+struct TypedBlock {
+    token_idx: usize,
+    start: Option<Vec<DataType>>,
+    branch: Option<Vec<DataType>>,
+}
+
+// Same walk as check_stack_range but over the types of the values. `inputs`
+// values of type Any are on the stack when the range starts, and the types
+// left on the stack at the end are returned, None if the end is unreachable.
+fn check_types_range(
+    program: &[Token],
+    range: Range<usize>,
+    inputs: usize,
+    effects: &HashMap<usize, Option<StackEffect>>,
+    signatures: &HashMap<usize, Vec<DataType>>,
+) -> Result<Option<Vec<DataType>>, (usize, String)> {
+    let mut stack: Option<Vec<DataType>> = Some(vec![DataType::Any; inputs]);
+    let mut blocks: Vec<TypedBlock> = Vec::new();
+    let mut token_idx = range.start;
+    while token_idx < range.end {
+        let token = &program[token_idx];
+        match &token.word {
+            Word::OpDefine(_, Some(after_end_idx)) => {
+                token_idx = *after_end_idx;
+                continue;
+            }
+            Word::OpCall(_, Some(define_idx)) => match effects.get(define_idx).copied().flatten() {
+                Some(effect) => {
+                    if let Some(types) = &mut stack {
+                        types.truncate(types.len().saturating_sub(effect.inputs));
+                        types.extend(&signatures[define_idx]);
+                    }
+                }
+                None => stack = None,
+            },
+            word => {
+                if let Some(types) = &mut stack {
+                    apply_types(word, types).map_err(|msg| (token_idx, msg))?;
+                }
+            }
+        }
+        match &token.word {
+            Word::OpIf(_) | Word::OpWhile | Word::OpDo(_) => blocks.push(TypedBlock {
+                token_idx,
+                start: stack.clone(),
+                branch: None,
+            }),
+            Word::OpElse(_) => {
+                let if_block = pop_block(&mut blocks, token_idx)?;
+                let then_types = std::mem::replace(&mut stack, if_block.start.clone());
+                blocks.push(TypedBlock {
+                    token_idx,
+                    start: if_block.start,
+                    branch: then_types,
+                });
+            }
+            Word::OpEnd(_) => {
+                let block = pop_block(&mut blocks, token_idx)?;
+                let (expected, what) = match program[block.token_idx].word {
+                    Word::OpIf(_) => (block.start, "'if' without 'else' must leave the same types on the stack"),
+                    Word::OpElse(_) => (block.branch, "'if' and 'else' branches leave different types on the stack"),
+                    Word::OpDo(_) => {
+                        let wile_block = pop_block(&mut blocks, token_idx)?;
+                        if let (Some(start), Some(end)) = (&wile_block.start, &stack) {
+                            if merge_types(start, end).is_none() {
+                                let msg = format!(
+                                    "'while' loop must leave the same types on the stack: {} before, {} after",
+                                    format_types(start),
+                                    format_types(end)
+                                );
+                                return Err((token_idx, msg));
+                            }
+                        }
+                        stack = block.start;
+                        token_idx += 1;
+                        continue;
+                    }
+                    _ => (None, ""),
+                };
+                stack = match (expected, stack) {
+                    (Some(expected), Some(found)) => match merge_types(&expected, &found) {
+                        Some(merged) => Some(merged),
+                        None => {
+                            let msg = format!("{what}: {} and {}", format_types(&expected), format_types(&found));
+                            return Err((token_idx, msg));
+                        }
+                    },
+                    (expected, found) => expected.or(found),
+                };
+            }
+            Word::OpExit => stack = None,
+            _ => (),
+        }
+        token_idx += 1;
+    }
+    Ok(stack)
+}
+
+// Optional check that every word gets values of the types it works on: ints,
+// bools produced by comparisons for 'if' and 'do', and pointers for memory
+// access. The output types of defined words are inferred like their stack
+// effects, starting from Any.
+pub(crate) fn check_types(program: &[Token], effects: &HashMap<usize, Option<StackEffect>>) -> Result<(), Diagnostic> {
+    let mut signatures: HashMap<usize, Vec<DataType>> = HashMap::new();
+    for (&define_idx, effect) in effects {
+        let outputs = effect.map_or(0, |effect| effect.outputs);
+        signatures.insert(define_idx, vec![DataType::Any; outputs]);
+    }
+    let definitions: Vec<(usize, usize, usize)> = program
+        .iter()
+        .enumerate()
+        .filter_map(|(token_idx, token)| match (&token.word, effects.get(&token_idx)) {
+            (Word::OpDefine(_, Some(after_end_idx)), Some(effect)) => {
+                Some((token_idx, *after_end_idx, effect.map_or(0, |effect| effect.inputs)))
+            }
+            _ => None,
+        })
+        .collect();
+    for _ in 0..=definitions.len() {
+        let mut changed = false;
+        for &(define_idx, after_end_idx, inputs) in &definitions {
+            let body = define_idx + 1..after_end_idx - 1;
+            if let Ok(Some(outputs)) = check_types_range(program, body, inputs, effects, &signatures) {
+                if signatures[&define_idx] != outputs {
+                    signatures.insert(define_idx, outputs);
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for &(define_idx, after_end_idx, inputs) in &definitions {
+        let body = define_idx + 1..after_end_idx - 1;
+        if let Err((token_idx, msg)) = check_types_range(program, body, inputs, effects, &signatures) {
+            return Err(token_diagnostic(&program[token_idx], "type", &msg));
+        }
+    }
+    if let Err((token_idx, msg)) = check_types_range(program, 0..program.len(), 0, effects, &signatures) {
+        return Err(token_diagnostic(&program[token_idx], "type", &msg));
+    }
+    Ok(())
+}
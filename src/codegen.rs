@@ -0,0 +1,642 @@
+use crate::diagnostic::token_diagnostic;
+use crate::word::Word;
+use crate::Error;
+use crate::Program;
+use crate::MEM_CAPACITY;
+use crate::RETURN_STACK_CAPACITY;
+
+/// Generates NASM assembly for x86-64 Linux, to be assembled with
+/// `nasm -felf64` and linked with `ld`.
+pub fn compile_to_asm(program: &Program) -> Result<String, Error> {
+    let program = &program.tokens;
+    let mut out = String::new();
+    out.push_str("BITS 64\n");
+    out.push_str("segment .text\n");
+    out.push_str("dump:\n");
+    out.push_str("    mov     r9, -3689348814741910323\n");
+    out.push_str("    sub     rsp, 40\n");
+    out.push_str("    mov     BYTE [rsp+31], 10\n");
+    out.push_str("    lea     rcx, [rsp+30]\n");
+    out.push_str("    mov     r10, rdi\n");
+    out.push_str("    test    rdi, rdi\n");
+    out.push_str("    jns     .L2\n");
+    out.push_str("    neg     rdi\n");
+    out.push_str(".L2:\n");
+    out.push_str("    mov     rax, rdi\n");
+    out.push_str("    lea     r8, [rsp+32]\n");
+    out.push_str("    mul     r9\n");
+    out.push_str("    mov     rax, rdi\n");
+    out.push_str("    sub     r8, rcx\n");
+    out.push_str("    shr     rdx, 3\n");
+    out.push_str("    lea     rsi, [rdx+rdx*4]\n");
+    out.push_str("    add     rsi, rsi\n");
+    out.push_str("    sub     rax, rsi\n");
+    out.push_str("    add     eax, 48\n");
+    out.push_str("    mov     BYTE [rcx], al\n");
+    out.push_str("    mov     rax, rdi\n");
+    out.push_str("    mov     rdi, rdx\n");
+    out.push_str("    mov     rdx, rcx\n");
+    out.push_str("    sub     rcx, 1\n");
+    out.push_str("    cmp     rax, 9\n");
+    out.push_str("    ja      .L2\n");
+    out.push_str("    test    r10, r10\n");
+    out.push_str("    jns     .L3\n");
+    out.push_str("    mov     BYTE [rdx-1], 45\n");
+    out.push_str("    sub     rdx, 1\n");
+    out.push_str("    add     r8, 1\n");
+    out.push_str(".L3:\n");
+    out.push_str("    lea     rax, [rsp+32]\n");
+    out.push_str("    mov     edi, 1\n");
+    out.push_str("    sub     rdx, rax\n");
+    out.push_str("    xor     eax, eax\n");
+    out.push_str("    lea     rsi, [rsp+32+rdx]\n");
+    out.push_str("    mov     rdx, r8\n");
+    out.push_str("    mov     rax, 1\n");
+    out.push_str("    syscall\n");
+    out.push_str("    add     rsp, 40\n");
+    out.push_str("    ret\n");
+    out.push_str("divmod:\n");
+    out.push_str("    test    rbx, rbx\n");
+    out.push_str("    jz      .by_zero\n");
+    out.push_str("    cmp     rbx, -1\n");
+    out.push_str("    je      .by_minus_one\n");
+    out.push_str("    cqo\n");
+    out.push_str("    idiv    rbx\n");
+    out.push_str("    ret\n");
+    out.push_str(".by_minus_one:\n");
+    out.push_str("    neg     rax\n");
+    out.push_str("    xor     edx, edx\n");
+    out.push_str("    ret\n");
+    out.push_str(".by_zero:\n");
+    out.push_str("    mov     rax, 1\n");
+    out.push_str("    mov     rdi, 2\n");
+    out.push_str("    mov     rsi, div_by_zero_msg\n");
+    out.push_str("    mov     rdx, div_by_zero_msg_len\n");
+    out.push_str("    syscall\n");
+    out.push_str("    mov     rax, 60\n");
+    out.push_str("    mov     rdi, 1\n");
+    out.push_str("    syscall\n");
+    out.push_str("ret_stack_overflow:\n");
+    out.push_str("    mov     rax, 1\n");
+    out.push_str("    mov     rdi, 2\n");
+    out.push_str("    mov     rsi, ret_stack_overflow_msg\n");
+    out.push_str("    mov     rdx, ret_stack_overflow_msg_len\n");
+    out.push_str("    syscall\n");
+    out.push_str("    mov     rax, 60\n");
+    out.push_str("    mov     rdi, 1\n");
+    out.push_str("    syscall\n");
+    out.push_str("accept:\n");
+    out.push_str("    mov     r12, rdi\n");
+    out.push_str("    mov     r13, rsi\n");
+    out.push_str("    xor     r14, r14\n");
+    out.push_str(".next:\n");
+    out.push_str("    cmp     r14, r13\n");
+    out.push_str("    jge     .done\n");
+    out.push_str("    mov     rax, 0\n");
+    out.push_str("    mov     rdi, 0\n");
+    out.push_str("    lea     rsi, [r12+r14]\n");
+    out.push_str("    mov     rdx, 1\n");
+    out.push_str("    syscall\n");
+    out.push_str("    cmp     rax, 1\n");
+    out.push_str("    jne     .done\n");
+    out.push_str("    cmp     BYTE [r12+r14], 10\n");
+    out.push_str("    je      .done\n");
+    out.push_str("    add     r14, 1\n");
+    out.push_str("    jmp     .next\n");
+    out.push_str(".done:\n");
+    out.push_str("    mov     rax, r14\n");
+    out.push_str("    ret\n");
+    out.push_str("global _start\n");
+    out.push_str("_start:\n");
+    out.push_str("    mov [args_ptr], rsp\n");
+    out.push_str("    mov rax, ret_stack_end\n");
+    out.push_str("    mov [ret_stack_rsp], rax\n");
+    let mut token_idx = 0;
+    while token_idx < program.len() {
+        let token = &program[token_idx];
+        let msg = format!("addr_{}:\n", token_idx);
+        out.push_str(&msg);
+        match token.word {
+            Word::OpPush(num) => {
+                let msg = format!("    ;; -- push {} --\n", num);
+                let inst = format!("    mov rax, {}\n", num);
+                out.push_str(msg.as_str());
+                out.push_str(inst.as_str());
+                out.push_str("    push rax\n");
+            }
+            Word::OpPlus => {
+                out.push_str("    ;; -- plus --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    add rax, rbx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpMinus => {
+                out.push_str("    ;; -- minus --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    sub rbx, rax\n");
+                out.push_str("    push rbx\n");
+            }
+            Word::OpEqual => {
+                out.push_str("    ;; -- equal -- \n");
+                out.push_str("    mov rcx, 0\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    cmp rax, rbx\n");
+                out.push_str("    cmove rcx, rdx\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpDump => {
+                out.push_str("    ;; -- dump --\n");
+                out.push_str("    pop rdi\n");
+                out.push_str("    call dump\n");
+            }
+            Word::OpDup => {
+                out.push_str("    ;; -- dup -- \n");
+                out.push_str("    pop rax\n");
+                out.push_str("    push rax\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpGt => {
+                out.push_str("    ;; -- gt --\n");
+                out.push_str("    mov rcx, 0\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    cmp rax, rbx\n");
+                out.push_str("    cmovg rcx, rdx\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpLt => {
+                out.push_str("    ;; -- lt --\n");
+                out.push_str("    mov rcx, 0\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    cmp rax, rbx\n");
+                out.push_str("    cmovl rcx, rdx\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpLe => {
+                out.push_str("    ;; -- le --\n");
+                out.push_str("    mov rcx, 0\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    cmp rax, rbx\n");
+                out.push_str("    cmovle rcx, rdx\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpGe => {
+                out.push_str("    ;; -- ge --\n");
+                out.push_str("    mov rcx, 0\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    cmp rax, rbx\n");
+                out.push_str("    cmovge rcx, rdx\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpNe => {
+                out.push_str("    ;; -- ne --\n");
+                out.push_str("    mov rcx, 0\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    cmp rax, rbx\n");
+                out.push_str("    cmovne rcx, rdx\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpZeroEqual => {
+                out.push_str("    ;; -- 0= --\n");
+                out.push_str("    mov rcx, 0\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    cmp rax, 0\n");
+                out.push_str("    cmove rcx, rdx\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpZeroLt => {
+                out.push_str("    ;; -- 0< --\n");
+                out.push_str("    mov rcx, 0\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    cmp rax, 0\n");
+                out.push_str("    cmovl rcx, rdx\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpZeroGt => {
+                out.push_str("    ;; -- 0> --\n");
+                out.push_str("    mov rcx, 0\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    cmp rax, 0\n");
+                out.push_str("    cmovg rcx, rdx\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpIf(else_end_idx) => {
+                out.push_str("    ;; -- if --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    test rax, rax\n");
+                let Some(else_end_idx) = else_end_idx 
+                        else {return Err(token_diagnostic(token, "internal", "'if' does not have reference to end of block").into())};
+                let msg = format!("    jz addr_{}\n", else_end_idx);
+                out.push_str(msg.as_str());
+            }
+            Word::OpElse(end_idx) => {
+                out.push_str("    ;; -- else --\n");
+                let Some(end_idx) = end_idx 
+                    else {return Err(token_diagnostic(token, "internal", "'else' does not have reference to end of block").into())};
+                let msg = format!("    jmp addr_{}\n", end_idx);
+                out.push_str(msg.as_str());
+            }
+            Word::OpEnd(wile_end_idx) => {
+                let Some(wile_end_idx) = wile_end_idx 
+                    else {return Err(token_diagnostic(token, "internal", "'end' does not have reference to while block or next instruction").into())};
+                out.push_str("    ;; -- end --\n");
+                if (token_idx + 1) != wile_end_idx {
+                    let msg = format!("    jmp addr_{}\n", wile_end_idx);
+                    out.push_str(msg.as_str());
+                }
+            }
+            Word::OpWhile => out.push_str("    ;; -- while --\n"),
+            Word::OpDo(end_idx) => {
+                out.push_str("    ;; -- do --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    test rax, rax\n");
+                let Some(end_idx) = end_idx
+                        else {return Err(token_diagnostic(token, "internal", "'do' does not have reference to end of block").into())};
+                let msg = format!("    jz addr_{}\n", end_idx);
+                out.push_str(msg.as_str());
+            }
+            Word::OpMul => {
+                out.push_str("    ;; -- mul --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    imul rax, rbx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpDiv => {
+                out.push_str("    ;; -- div --\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    call divmod\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpMod => {
+                out.push_str("    ;; -- mod --\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    call divmod\n");
+                out.push_str("    push rdx\n");
+            }
+            Word::OpDivMod => {
+                out.push_str("    ;; -- /mod --\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    call divmod\n");
+                out.push_str("    push rdx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpNegate => {
+                out.push_str("    ;; -- negate --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    neg rax\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpAbs => {
+                out.push_str("    ;; -- abs --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    mov rbx, rax\n");
+                out.push_str("    neg rbx\n");
+                out.push_str("    cmovs rbx, rax\n");
+                out.push_str("    push rbx\n");
+            }
+            Word::OpMin => {
+                out.push_str("    ;; -- min --\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    cmp rax, rbx\n");
+                out.push_str("    cmovg rax, rbx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpMax => {
+                out.push_str("    ;; -- max --\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    cmp rax, rbx\n");
+                out.push_str("    cmovl rax, rbx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpAnd => {
+                out.push_str("    ;; -- and --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    and rax, rbx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpOr => {
+                out.push_str("    ;; -- or --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    or rax, rbx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpXor => {
+                out.push_str("    ;; -- xor --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    xor rax, rbx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpNot => {
+                out.push_str("    ;; -- not --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    not rax\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpShl => {
+                out.push_str("    ;; -- shl --\n");
+                out.push_str("    pop rcx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    shl rax, cl\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpShr => {
+                out.push_str("    ;; -- shr --\n");
+                out.push_str("    pop rcx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    shr rax, cl\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpSar => {
+                out.push_str("    ;; -- sar --\n");
+                out.push_str("    pop rcx\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    sar rax, cl\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpSwap => {
+                out.push_str("    ;; -- swap --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    push rax\n");
+                out.push_str("    push rbx\n");
+            }
+            Word::OpDrop => {
+                out.push_str("    ;; -- drop --\n");
+                out.push_str("    pop rax\n");
+            }
+            Word::OpOver => {
+                out.push_str("    ;; -- over --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    push rbx\n");
+                out.push_str("    push rax\n");
+                out.push_str("    push rbx\n");
+            }
+            Word::OpRot => {
+                out.push_str("    ;; -- rot --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rcx\n");
+                out.push_str("    push rbx\n");
+                out.push_str("    push rax\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpMinusRot => {
+                out.push_str("    ;; -- -rot --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rcx\n");
+                out.push_str("    push rax\n");
+                out.push_str("    push rcx\n");
+                out.push_str("    push rbx\n");
+            }
+            Word::OpNip => {
+                out.push_str("    ;; -- nip --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpTuck => {
+                out.push_str("    ;; -- tuck --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    push rax\n");
+                out.push_str("    push rbx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpTwoDup => {
+                out.push_str("    ;; -- 2dup --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    push rbx\n");
+                out.push_str("    push rax\n");
+                out.push_str("    push rbx\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpTwoDrop => {
+                out.push_str("    ;; -- 2drop --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rax\n");
+            }
+            Word::OpTwoSwap => {
+                out.push_str("    ;; -- 2swap --\n");
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    pop rcx\n");
+                out.push_str("    pop rdx\n");
+                out.push_str("    push rbx\n");
+                out.push_str("    push rax\n");
+                out.push_str("    push rdx\n");
+                out.push_str("    push rcx\n");
+            }
+            Word::OpPushStr(ref text) => {
+                out.push_str("    ;; -- push str --\n");
+                let inst = format!("    mov rax, {}\n", text.len());
+                out.push_str(inst.as_str());
+                out.push_str("    push rax\n");
+                let inst = format!("    mov rax, str_{}\n", token_idx);
+                out.push_str(inst.as_str());
+                out.push_str("    push rax\n");
+            }
+            Word::OpPuts => {
+                out.push_str("    ;; -- puts --\n");
+                out.push_str("    mov rax, 1\n");
+                out.push_str("    mov rdi, 1\n");
+                out.push_str("    pop rsi\n");
+                out.push_str("    pop rdx\n");
+                out.push_str("    syscall\n");
+            }
+            Word::OpEmit => {
+                out.push_str("    ;; -- emit --\n");
+                out.push_str("    mov rax, 1\n");
+                out.push_str("    mov rdi, 1\n");
+                out.push_str("    mov rsi, rsp\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    syscall\n");
+                out.push_str("    pop rax\n");
+            }
+            Word::OpKey => {
+                out.push_str("    ;; -- key --\n");
+                out.push_str("    push 0\n");
+                out.push_str("    mov rax, 0\n");
+                out.push_str("    mov rdi, 0\n");
+                out.push_str("    mov rsi, rsp\n");
+                out.push_str("    mov rdx, 1\n");
+                out.push_str("    syscall\n");
+                out.push_str("    pop rbx\n");
+                out.push_str("    mov rcx, -1\n");
+                out.push_str("    cmp rax, 1\n");
+                out.push_str("    cmovne rbx, rcx\n");
+                out.push_str("    push rbx\n");
+            }
+            Word::OpAccept => {
+                out.push_str("    ;; -- accept --\n");
+                out.push_str("    pop rsi\n");
+                out.push_str("    pop rdi\n");
+                out.push_str("    call accept\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpMem => {
+                out.push_str("    ;; -- mem --\n");
+                out.push_str("    mov rax, mem\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpLoad(width) => {
+                let msg = format!("    ;; -- load{} --\n", width * 8);
+                out.push_str(msg.as_str());
+                out.push_str("    pop rax\n");
+                match width {
+                    1 => out.push_str("    movzx rbx, BYTE [rax]\n"),
+                    2 => out.push_str("    movzx rbx, WORD [rax]\n"),
+                    4 => out.push_str("    mov ebx, DWORD [rax]\n"),
+                    _ => out.push_str("    mov rbx, QWORD [rax]\n"),
+                }
+                out.push_str("    push rbx\n");
+            }
+            Word::OpStore(width) => {
+                let msg = format!("    ;; -- store{} --\n", width * 8);
+                out.push_str(msg.as_str());
+                out.push_str("    pop rax\n");
+                out.push_str("    pop rbx\n");
+                match width {
+                    1 => out.push_str("    mov [rax], bl\n"),
+                    2 => out.push_str("    mov [rax], bx\n"),
+                    4 => out.push_str("    mov [rax], ebx\n"),
+                    _ => out.push_str("    mov [rax], rbx\n"),
+                }
+            }
+            Word::OpSyscall(arg_count) => {
+                let msg = format!("    ;; -- syscall{} --\n", arg_count);
+                out.push_str(msg.as_str());
+                out.push_str("    pop rax\n");
+                for reg in ["rdi", "rsi", "rdx", "r10", "r8", "r9"].iter().take(arg_count) {
+                    let inst = format!("    pop {}\n", reg);
+                    out.push_str(inst.as_str());
+                }
+                out.push_str("    syscall\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpExit => {
+                out.push_str("    ;; -- exit --\n");
+                out.push_str("    mov rax, 60\n");
+                out.push_str("    pop rdi\n");
+                out.push_str("    syscall\n");
+            }
+            Word::OpArgc => {
+                out.push_str("    ;; -- argc --\n");
+                out.push_str("    mov rax, [args_ptr]\n");
+                out.push_str("    push QWORD [rax]\n");
+            }
+            Word::OpArgv => {
+                out.push_str("    ;; -- argv --\n");
+                out.push_str("    mov rax, [args_ptr]\n");
+                out.push_str("    add rax, 8\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpEnvp => {
+                out.push_str("    ;; -- envp --\n");
+                out.push_str("    mov rax, [args_ptr]\n");
+                out.push_str("    mov rbx, [rax]\n");
+                out.push_str("    lea rax, [rax+rbx*8+16]\n");
+                out.push_str("    push rax\n");
+            }
+            Word::OpCast(_) => {
+                out.push_str("    ;; -- cast --\n");
+            }
+            Word::OpConst(_) => {
+                return Err(token_diagnostic(token, "internal", "'const' should have been evaluated before compilation").into());
+            }
+            Word::OpMacro(_) => {
+                return Err(token_diagnostic(token, "internal", "'macro' should have been expanded before compilation").into());
+            }
+            Word::OpDefine(ref name, after_end_idx) => {
+                let Some(after_end_idx) = after_end_idx
+                        else {return Err(token_diagnostic(token, "internal", "':' does not have reference to end of definition").into())};
+                let msg = format!("    ;; -- define {} --\n", name);
+                out.push_str(msg.as_str());
+                let msg = format!("    jmp addr_{}\n", after_end_idx);
+                out.push_str(msg.as_str());
+                let msg = format!("proc_{}:\n", token_idx);
+                out.push_str(msg.as_str());
+                out.push_str("    mov [ret_stack_rsp], rsp\n");
+                out.push_str("    mov rsp, rax\n");
+            }
+            Word::OpCall(ref name, define_idx) => {
+                let Some(define_idx) = define_idx
+                        else {return Err(token_diagnostic(token, "internal", "call does not have reference to definition").into())};
+                let msg = format!("    ;; -- call {} --\n", name);
+                out.push_str(msg.as_str());
+                out.push_str("    mov rax, rsp\n");
+                out.push_str("    mov rsp, [ret_stack_rsp]\n");
+                // the return address must not be pushed below ret_stack, onto
+                // the variables in front of it
+                out.push_str("    lea rbx, [ret_stack + 8]\n");
+                out.push_str("    cmp rsp, rbx\n");
+                out.push_str("    jb ret_stack_overflow\n");
+                let msg = format!("    call proc_{}\n", define_idx);
+                out.push_str(msg.as_str());
+                out.push_str("    mov [ret_stack_rsp], rsp\n");
+                out.push_str("    mov rsp, rax\n");
+            }
+            Word::OpReturn => {
+                out.push_str("    ;; -- return --\n");
+                out.push_str("    mov rax, rsp\n");
+                out.push_str("    mov rsp, [ret_stack_rsp]\n");
+                out.push_str("    ret\n");
+            }
+        }
+        token_idx += 1;
+    }
+
+    let msg = format!("addr_{}:\n", program.len());
+    out.push_str(&msg);
+    out.push_str("    mov rax, 60\n");
+    out.push_str("    mov rdi, 0\n");
+    out.push_str("    syscall\n");
+    out.push_str("segment .data\n");
+    out.push_str("div_by_zero_msg: db \"Error: division by zero\", 10\n");
+    out.push_str("div_by_zero_msg_len equ $ - div_by_zero_msg\n");
+    out.push_str("ret_stack_overflow_msg: db \"Error: return stack overflow\", 10\n");
+    out.push_str("ret_stack_overflow_msg_len equ $ - ret_stack_overflow_msg\n");
+    for (token_idx, token) in program.iter().enumerate() {
+        if let Word::OpPushStr(text) = &token.word {
+            let bytes: Vec<String> = text.bytes().chain([0]).map(|byte| byte.to_string()).collect();
+            let msg = format!("str_{}: db {}\n", token_idx, bytes.join(","));
+            out.push_str(msg.as_str());
+        }
+    }
+    out.push_str("segment .bss\n");
+    out.push_str("args_ptr: resq 1\n");
+    out.push_str("ret_stack_rsp: resq 1\n");
+    let msg = format!("ret_stack: resq {}\n", RETURN_STACK_CAPACITY);
+    out.push_str(msg.as_str());
+    out.push_str("ret_stack_end:\n");
+    let msg = format!("mem: resb {}\n", MEM_CAPACITY);
+    out.push_str(msg.as_str());
+    Ok(out)
+}
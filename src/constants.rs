@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::token_diagnostic;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Token;
+use crate::word::*;
+
+fn pop_constant(stack: &mut Vec<i64>, token: &Token) -> Result<i64, Diagnostic> {
+    stack.pop().ok_or_else(|| token_diagnostic(token, "const", "stack is empty"))
+}
+
+fn evaluate_constant(
+    const_token: &Token,
+    body: &[Token],
+    constants: &HashMap<String, i64>,
+) -> Result<i64, Diagnostic> {
+    let mut stack: Vec<i64> = Vec::new();
+    for token in body {
+        let value = match &token.word {
+            Word::OpPush(num) => *num,
+            Word::OpCall(name, _) if constants.contains_key(name) => constants[name],
+            Word::OpCall(name, _) => {
+                return Err(token_diagnostic(token, "const", &format!("unknown constant '{name}'")));
+            }
+            Word::OpNegate | Word::OpAbs | Word::OpNot => {
+                let a = pop_constant(&mut stack, token)?;
+                match token.word {
+                    Word::OpNegate => a.wrapping_neg(),
+                    Word::OpAbs => a.wrapping_abs(),
+                    _ => !a,
+                }
+            }
+            Word::OpPlus | Word::OpMinus | Word::OpMul | Word::OpDiv | Word::OpMod | Word::OpMin
+            | Word::OpMax | Word::OpAnd | Word::OpOr | Word::OpXor | Word::OpShl | Word::OpShr
+            | Word::OpSar => {
+                let a = pop_constant(&mut stack, token)?;
+                let b = pop_constant(&mut stack, token)?;
+                if a == 0 && matches!(token.word, Word::OpDiv | Word::OpMod) {
+                    return Err(token_diagnostic(token, "const", "division by zero"));
+                }
+                match token.word {
+                    Word::OpPlus => b.wrapping_add(a),
+                    Word::OpMinus => b.wrapping_sub(a),
+                    Word::OpMul => b.wrapping_mul(a),
+                    Word::OpDiv => b.wrapping_div(a),
+                    Word::OpMod => b.wrapping_rem(a),
+                    Word::OpMin => b.min(a),
+                    Word::OpMax => b.max(a),
+                    Word::OpAnd => b & a,
+                    Word::OpOr => b | a,
+                    Word::OpXor => b ^ a,
+                    Word::OpShl => b.wrapping_shl(a as u32),
+                    Word::OpShr => (b as u64).wrapping_shr(a as u32) as i64,
+                    _ => b.wrapping_shr(a as u32),
+                }
+            }
+            _ => {
+                return Err(token_diagnostic(token, "const", "word is not supported in constant expressions"));
+            }
+        };
+        stack.push(value);
+    }
+    if stack.len() != 1 {
+        let msg = format!("constant expression must produce exactly one value, got {}", stack.len());
+        return Err(token_diagnostic(const_token, "const", &msg));
+    }
+    Ok(stack[0])
+}
+
+pub(crate) fn evaluate_constants(program: Vec<Token>, errors: &mut Vec<Diagnostic>) -> Vec<Token> {
+    let mut constants: HashMap<String, i64> = HashMap::new();
+    let mut out_program: Vec<Token> = Vec::new();
+    let mut token_idx = 0;
+    while token_idx < program.len() {
+        let token = &program[token_idx];
+        match &token.word {
+            Word::OpConst(name) => {
+                let Some(body_len) = program[token_idx + 1..]
+                    .iter()
+                    .position(|token| matches!(token.word, Word::OpEnd(_)))
+                else {
+                    errors.push(token_diagnostic(token, "const", "'const' is missing its 'end'"));
+                    break;
+                };
+                let is_word = |token: &Token| matches!(&token.word, Word::OpDefine(word, _) if word == name);
+                if constants.contains_key(name) || program.iter().any(is_word) {
+                    errors.push(token_diagnostic(token, "const", &format!("'{name}' is already defined")));
+                } else {
+                    // a constant that fails to evaluate is still defined so its uses are not reported too
+                    let body = &program[token_idx + 1..token_idx + 1 + body_len];
+                    let value = evaluate_constant(token, body, &constants).unwrap_or_else(|diagnostic| {
+                        errors.push(diagnostic);
+                        0
+                    });
+                    constants.insert(name.to_owned(), value);
+                }
+                token_idx += body_len + 2;
+                continue;
+            }
+            Word::OpCall(name, _) if constants.contains_key(name) => {
+                out_program.push(Token {
+                    word: push(constants[name]),
+                    ..(*token).clone()
+                });
+            }
+            _ => {
+                out_program.push(Token { ..(*token).clone() });
+            }
+        }
+        token_idx += 1;
+    }
+    out_program
+}
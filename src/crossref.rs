@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::token_diagnostic;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Token;
+use crate::word::*;
+
+fn collect_definitions(program: &[Token], errors: &mut Vec<Diagnostic>) -> HashMap<String, usize> {
+    let mut definitions: HashMap<String, usize> = HashMap::new();
+    for (token_idx, token) in program.iter().enumerate() {
+        if let Word::OpDefine(name, _) = &token.word {
+            if let Some(&prev_idx) = definitions.get(name) {
+                let prev = &program[prev_idx];
+                let msg = format!(
+                    "word '{name}' is already defined at {}:{}:{}",
+                    prev.file_path, prev.row, prev.col
+                );
+                errors.push(token_diagnostic(token, "definition", &msg));
+            } else {
+                definitions.insert(name.to_owned(), token_idx);
+            }
+        }
+    }
+    definitions
+}
+
+fn block_keyword(word: &Word) -> &'static str {
+    match word {
+        Word::OpIf(_) => "if",
+        Word::OpElse(_) => "else",
+        Word::OpWhile => "while",
+        Word::OpDo(_) => "do",
+        _ => ":",
+    }
+}
+
+// Resolves the jump targets of blocks and calls. Every token is kept, even
+// when it is reported as an error, so indices stay the same as in `program`.
+pub(crate) fn crossreference_blocks(program: Vec<Token>, errors: &mut Vec<Diagnostic>) -> Vec<Token> {
+    let definitions = collect_definitions(&program, errors);
+    let mut stack: Vec<usize> = Vec::new();
+    let mut out_program: Vec<Token> = Vec::new();
+    for token_idx in 0..program.len() {
+        let token = &program[token_idx];
+        out_program.push(token.clone());
+        match &token.word {
+            Word::OpIf(_else_end_idx) => {
+                stack.push(token_idx);
+            }
+            Word::OpElse(_end_idx) => {
+                let Some(&if_idx) = stack.last().filter(|&&idx| matches!(program[idx].word, Word::OpIf(_))) else {
+                    let msg = match stack.last().map(|&idx| &program[idx].word) {
+                        Some(Word::OpElse(_)) => "'if' already has an 'else'",
+                        _ => "'else' without matching 'if'",
+                    };
+                    errors.push(token_diagnostic(token, "block", msg));
+                    continue;
+                };
+                stack.pop();
+                out_program[if_idx].word = iff(Some(token_idx + 1));
+                stack.push(token_idx);
+            }
+            Word::OpEnd(mut _wile_end_idx) => {
+                let Some(block_idx) = stack.pop() else {
+                    errors.push(token_diagnostic(token, "block", "'end' without an open block"));
+                    continue;
+                };
+                match program[block_idx].word {
+                    Word::OpIf(_end_idx) => {
+                        out_program[block_idx].word = iff(Some(token_idx));
+                        out_program[token_idx].word = end(Some(token_idx + 1));
+                    }
+                    Word::OpElse(_end_idx) => {
+                        out_program[block_idx].word = elze(Some(token_idx));
+                        out_program[token_idx].word = end(Some(token_idx + 1));
+                    }
+                    Word::OpDo(_wile_idx) => {
+                        // a 'do' without 'while' was already reported and has no loop to jump back to
+                        if let Word::OpDo(Some(wile_idx)) = out_program[block_idx].word {
+                            out_program[block_idx].word = doo(Some(token_idx + 1));
+                            out_program[token_idx].word = end(Some(wile_idx));
+                        };
+                    }
+                    Word::OpWhile => {
+                        errors.push(token_diagnostic(token, "block", "'end' closes a 'while' that has no 'do'"));
+                    }
+                    _ => {
+                        let msg = "'end' can only close 'if', 'else' or 'do' blocks";
+                        errors.push(token_diagnostic(token, "block", msg));
+                        stack.push(block_idx);
+                    }
+                }
+            }
+            Word::OpWhile => {
+                stack.push(token_idx);
+            }
+            Word::OpDo(_wile_end_idx) => {
+                // the 'do' is kept open even without a 'while' so that its 'end' matches it
+                match stack.last().map(|&idx| &program[idx].word) {
+                    Some(Word::OpWhile) => {
+                        let wile_idx = stack.pop();
+                        out_program[token_idx].word = doo(wile_idx);
+                    }
+                    _ => errors.push(token_diagnostic(token, "block", "'do' without matching 'while'")),
+                }
+                stack.push(token_idx);
+            }
+            Word::OpDefine(_name, _after_end_idx) => {
+                if !stack.is_empty() {
+                    errors.push(token_diagnostic(token, "definition", "words can only be defined at top level"));
+                }
+                stack.push(token_idx);
+            }
+            Word::OpReturn => {
+                let is_define = |&block_idx: &usize| matches!(program[block_idx].word, Word::OpDefine(_, _));
+                if !stack.iter().any(is_define) {
+                    errors.push(token_diagnostic(token, "block", "';' without matching ':'"));
+                    continue;
+                }
+                // blocks still open inside the definition end with it
+                while let Some(block_idx) = stack.pop() {
+                    let block = &program[block_idx];
+                    if let Word::OpDefine(name, _) = &block.word {
+                        out_program[block_idx].word = define(name.to_owned(), Some(token_idx + 1));
+                        break;
+                    }
+                    let msg = format!("'{}' is not closed before ';'", block_keyword(&block.word));
+                    errors.push(token_diagnostic(block, "block", &msg));
+                }
+            }
+            Word::OpCall(name, _define_idx) => {
+                let Some(&define_idx) = definitions.get(name) else {
+                    errors.push(token_diagnostic(token, "unknown-word", &format!("unknown word '{name}'")));
+                    continue;
+                };
+                out_program[token_idx].word = call(name.to_owned(), Some(define_idx));
+            }
+            _ => (),
+        }
+    }
+    for block_idx in stack {
+        let block = &program[block_idx];
+        let msg = match block.word {
+            Word::OpDefine(_, _) => "':' is never closed, expected ';'".to_owned(),
+            Word::OpWhile => "'while' is never followed by 'do'".to_owned(),
+            _ => format!("'{}' is never closed, expected 'end'", block_keyword(&block.word)),
+        };
+        errors.push(token_diagnostic(block, "block", &msg));
+    }
+    out_program
+}
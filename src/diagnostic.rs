@@ -0,0 +1,187 @@
+use std::fmt;
+use std::fs;
+
+use crate::lexer::Token;
+
+const MAX_EXPANSION_NOTES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub file_path: String,
+    pub row: usize,
+    pub col: usize,
+    // length in characters, the span never covers more than one line
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // kind of error for tools, like "syntax", "block" or "stack"
+    pub code: &'static str,
+    pub span: Option<Span>,
+    pub message: String,
+    pub notes: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, span: Option<Span>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            span,
+            message: message.into(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note(mut self, span: Option<Span>, message: impl Into<String>) -> Diagnostic {
+        self.notes.push(Diagnostic {
+            severity: Severity::Note,
+            code: self.code,
+            span,
+            message: message.into(),
+            notes: Vec::new(),
+        });
+        self
+    }
+
+    // Errors show the offending line with the span underlined, notes are one
+    // line each below it:
+    //
+    //     Error: file.rf:1:3: stack underflow: needs 2 values but the stack has 1
+    //       |
+    //     1 | 1 +
+    //       |   ^
+    //         note: file.rf:2:3: expanded from macro 'm'
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        match &self.span {
+            Some(span) => {
+                let loc = format!("{}:{}:{}", span.file_path, span.row, span.col);
+                out.push_str(&format!("{}: {loc}: {}\n", self.severity, self.message));
+            }
+            None => out.push_str(&format!("{}: {}\n", self.severity, self.message)),
+        }
+        if let Some(snippet) = self.span.as_ref().and_then(render_snippet) {
+            out.push_str(&snippet);
+        }
+        for note in &self.notes {
+            let mut note = note.render();
+            note.truncate(note.find('\n').map_or(note.len(), |line_end| line_end + 1));
+            out.push_str("    ");
+            out.push_str(&note);
+        }
+        out
+    }
+
+    // One object per line with the span as 1-based row and column, the end
+    // column is exclusive. Diagnostics without a location have null fields.
+    pub fn render_json(&self) -> String {
+        let mut fields: Vec<String> = Vec::new();
+        match &self.span {
+            Some(span) => {
+                fields.push(format!("\"file\":{}", json_string(&span.file_path)));
+                fields.push(format!("\"row\":{},\"col\":{}", span.row, span.col));
+                fields.push(format!("\"end_row\":{},\"end_col\":{}", span.row, span.col + span.len));
+            }
+            None => fields.push("\"file\":null,\"row\":null,\"col\":null,\"end_row\":null,\"end_col\":null".to_owned()),
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Note => "note",
+        };
+        fields.push(format!("\"severity\":\"{severity}\""));
+        fields.push(format!("\"code\":{}", json_string(self.code)));
+        fields.push(format!("\"message\":{}", json_string(&self.message)));
+        if self.severity != Severity::Note {
+            let notes: Vec<String> = self.notes.iter().map(|note| note.render_json()).collect();
+            fields.push(format!("\"notes\":[{}]", notes.join(",")));
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for char in text.chars() {
+        match char {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            char if char.is_control() => out.push_str(&format!("\\u{:04x}", char as u32)),
+            char => out.push(char),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn render_snippet(span: &Span) -> Option<String> {
+    let source = fs::read_to_string(&span.file_path).ok()?;
+    let line = source.lines().nth(span.row.checked_sub(1)?)?;
+    let gutter = " ".repeat(span.row.to_string().len());
+    // keep tabs so the carets line up with the source line
+    let padding: String = line
+        .chars()
+        .take(span.col.saturating_sub(1))
+        .map(|char| if char == '\t' { '\t' } else { ' ' })
+        .collect();
+    let carets = "^".repeat(span.len.max(1));
+    Some(format!("{gutter} |\n{} | {line}\n{gutter} | {padding}{carets}\n", span.row))
+}
+
+// Long expansion chains keep the innermost notes and the outermost one, which
+// is where the expansion started in the user's code.
+pub(crate) fn token_diagnostic(token: &Token, code: &'static str, msg: &str) -> Diagnostic {
+    let mut diagnostic = Diagnostic::error(code, Some(token.span()), msg);
+    let expansions = &token.expanded_from;
+    let shown = if expansions.len() > MAX_EXPANSION_NOTES { MAX_EXPANSION_NOTES - 1 } else { expansions.len() };
+    for expansion in &expansions[..shown] {
+        let msg = format!("expanded from macro '{}'", expansion.macro_name);
+        diagnostic = diagnostic.with_note(Some(expansion.span.clone()), msg);
+    }
+    if let Some(outermost) = expansions.get(shown..).and_then(|rest| rest.last()) {
+        let hidden = expansions.len() - shown - 1;
+        if hidden > 0 {
+            diagnostic = diagnostic.with_note(None, format!("... and {hidden} more macro expansions"));
+        }
+        let msg = format!("expanded from macro '{}'", outermost.macro_name);
+        diagnostic = diagnostic.with_note(Some(outermost.span.clone()), msg);
+    }
+    diagnostic
+}
+
+pub(crate) fn span_diagnostic(
+    program_path: &str,
+    code: &'static str,
+    row: usize,
+    col: usize,
+    len: usize,
+    msg: &str,
+) -> Diagnostic {
+    let span = Span {
+        file_path: program_path.to_owned(),
+        row,
+        col,
+        len,
+    };
+    Diagnostic::error(code, Some(span), msg)
+}
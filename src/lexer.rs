@@ -0,0 +1,438 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::diagnostic::span_diagnostic;
+use crate::diagnostic::Diagnostic;
+use crate::diagnostic::Span;
+use crate::word::*;
+
+#[derive(Debug, Clone)]
+pub struct Expansion {
+    pub macro_name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub file_path: String,
+    pub col: usize,
+    pub row: usize,
+    pub len: usize,
+    pub word: Word,
+    // macro expansions this token came from, innermost first
+    pub expanded_from: Vec<Expansion>,
+}
+
+impl Token {
+    pub fn span(&self) -> Span {
+        Span {
+            file_path: self.file_path.to_owned(),
+            row: self.row,
+            col: self.col,
+            len: self.len,
+        }
+    }
+}
+
+fn skip_line_comment(chars: &[char], mut char_idx: usize) -> usize {
+    while char_idx < chars.len() && chars[char_idx] != '\n' {
+        char_idx += 1;
+    }
+    char_idx
+}
+
+fn find_words(program_path: &str, source: &str, errors: &mut Vec<Diagnostic>) -> Vec<(String, usize, usize)> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut words: Vec<(String, usize, usize)> = Vec::new();
+
+    let mut char_idx = 0;
+    let mut row_no = 1;
+    let mut col_no = 1;
+    while char_idx < chars.len() {
+        let char = chars[char_idx];
+        if char == '\n' {
+            char_idx += 1;
+            row_no += 1;
+            col_no = 1;
+            continue;
+        }
+        if char.is_whitespace() {
+            char_idx += 1;
+            col_no += 1;
+            continue;
+        }
+
+        let (word_row, word_col) = (row_no, col_no);
+        let mut word = String::new();
+        if char == '"' || char == '\'' {
+            // string or character literal, kept with its quotes and escapes for lex_file to decode
+            let quote = char;
+            word.push(char);
+            char_idx += 1;
+            col_no += 1;
+            let mut terminated = false;
+            while let Some(&char) = chars.get(char_idx).filter(|&&char| char != '\n') {
+                word.push(char);
+                char_idx += 1;
+                col_no += 1;
+                if char == quote {
+                    terminated = true;
+                    break;
+                }
+                if char == '\\' {
+                    if let Some(&escaped) = chars.get(char_idx).filter(|&&char| char != '\n') {
+                        word.push(escaped);
+                        char_idx += 1;
+                        col_no += 1;
+                    }
+                }
+            }
+            if terminated {
+                words.push((word, word_row, word_col));
+            } else {
+                let kind = if quote == '"' { "string" } else { "character" };
+                let msg = format!("unterminated {kind} literal");
+                errors.push(span_diagnostic(program_path, "syntax", word_row, word_col, word.chars().count(), &msg));
+            }
+            continue;
+        }
+        while char_idx < chars.len() && !chars[char_idx].is_whitespace() {
+            word.push(chars[char_idx]);
+            char_idx += 1;
+            col_no += 1;
+        }
+
+        if word == "\\" || word.starts_with("//") {
+            // line comment, the newline itself is handled above
+            let comment_end = skip_line_comment(&chars, char_idx);
+            col_no += comment_end - char_idx;
+            char_idx = comment_end;
+        } else if word == "(" {
+            // block comment, may span several lines and ends at the first ')'
+            let mut terminated = false;
+            while let Some(&char) = chars.get(char_idx) {
+                char_idx += 1;
+                if char == '\n' {
+                    row_no += 1;
+                    col_no = 1;
+                } else {
+                    col_no += 1;
+                }
+                if char == ')' {
+                    terminated = true;
+                    break;
+                }
+            }
+            if !terminated {
+                let msg = "unterminated comment, expected ')'";
+                errors.push(span_diagnostic(program_path, "syntax", word_row, word_col, 1, msg));
+            }
+        } else {
+            words.push((word, word_row, word_col));
+        }
+    }
+    words
+}
+
+fn unescape(program_path: &str, row_no: usize, col_no: usize, text: &str, errors: &mut Vec<Diagnostic>) -> String {
+    // errors underline the whole literal, quotes included
+    let literal_len = text.chars().count() + 2;
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            unescaped.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('0') => unescaped.push('\0'),
+            Some('"') => unescaped.push('"'),
+            Some('\'') => unescaped.push('\''),
+            Some('\\') => unescaped.push('\\'),
+            Some(other) => {
+                let msg = format!("unknown escape sequence '\\{other}'");
+                errors.push(span_diagnostic(program_path, "syntax", row_no, col_no, literal_len, &msg));
+            }
+            None => {
+                let msg = "unfinished escape sequence";
+                errors.push(span_diagnostic(program_path, "syntax", row_no, col_no, literal_len, msg));
+            }
+        }
+    }
+    unescaped
+}
+
+fn lex_word(word: &str) -> Option<Word> {
+    match word {
+        "+" => Some(plus()),
+        "-" => Some(minus()),
+        "." => Some(dump()),
+        "=" => Some(equal()),
+        "dup" => Some(dup()),
+        ">" => Some(gt()),
+        "<" => Some(lt()),
+        "<=" => Some(le()),
+        ">=" => Some(ge()),
+        "!=" => Some(ne()),
+        "0=" => Some(zero_equal()),
+        "0<" => Some(zero_lt()),
+        "0>" => Some(zero_gt()),
+        "if" => Some(iff(None)),
+        "end" => Some(end(None)),
+        "else" => Some(elze(None)),
+        "while" => Some(wile()),
+        "do" => Some(doo(None)),
+        "*" => Some(mul()),
+        "/" => Some(div()),
+        "mod" => Some(modd()),
+        "/mod" => Some(divmod()),
+        "negate" => Some(negate()),
+        "abs" => Some(abs()),
+        "min" => Some(min()),
+        "max" => Some(max()),
+        "and" => Some(and()),
+        "or" => Some(or()),
+        "xor" => Some(xor()),
+        "not" | "invert" => Some(not()),
+        "shl" => Some(shl()),
+        "shr" => Some(shr()),
+        "sar" => Some(sar()),
+        "swap" => Some(swap()),
+        "drop" => Some(dropp()),
+        "over" => Some(over()),
+        "rot" => Some(rot()),
+        "-rot" => Some(minus_rot()),
+        "nip" => Some(nip()),
+        "tuck" => Some(tuck()),
+        "2dup" => Some(two_dup()),
+        "2drop" => Some(two_drop()),
+        "2swap" => Some(two_swap()),
+        "puts" | "type" => Some(puts()),
+        "emit" => Some(emit()),
+        "key" => Some(key()),
+        "accept" => Some(accept()),
+        "mem" => Some(mem()),
+        "c@" => Some(load(1)),
+        "w@" => Some(load(2)),
+        "l@" => Some(load(4)),
+        "@" => Some(load(8)),
+        "c!" => Some(store(1)),
+        "w!" => Some(store(2)),
+        "l!" => Some(store(4)),
+        "!" => Some(store(8)),
+        "syscall0" => Some(syscall(0)),
+        "syscall1" => Some(syscall(1)),
+        "syscall2" => Some(syscall(2)),
+        "syscall3" => Some(syscall(3)),
+        "syscall4" => Some(syscall(4)),
+        "syscall5" => Some(syscall(5)),
+        "syscall6" => Some(syscall(6)),
+        "exit" => Some(exitt()),
+        "argc" => Some(argc()),
+        "argv" => Some(argv()),
+        "envp" => Some(envp()),
+        "cast(int)" => Some(cast(DataType::Int)),
+        "cast(bool)" => Some(cast(DataType::Bool)),
+        "cast(ptr)" => Some(cast(DataType::Ptr)),
+        ";" => Some(ret()),
+        _ => None,
+    }
+}
+
+fn is_reserved(word: &str) -> bool {
+    matches!(word, ":" | "const" | "macro" | "include") || lex_word(word).is_some()
+}
+
+fn looks_like_number(word: &str) -> bool {
+    let digits = word.strip_prefix(['-', '+']).unwrap_or(word);
+    digits.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn parse_number(word: &str) -> Result<i64, &'static str> {
+    let (negative, unsigned) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word.strip_prefix('+').unwrap_or(word)),
+    };
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x" | "0X") => (16, &unsigned[2..]),
+        Some("0o" | "0O") => (8, &unsigned[2..]),
+        Some("0b" | "0B") => (2, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+    if digits.is_empty() {
+        return Err("digits expected");
+    }
+    if digits.starts_with('_') || digits.ends_with('_') {
+        return Err("misplaced '_' separator");
+    }
+    if !digits.chars().all(|char| char == '_' || char.is_digit(radix)) {
+        return Err("invalid digit");
+    }
+    let digits: String = digits.chars().filter(|&char| char != '_').collect();
+    let Ok(magnitude) = u64::from_str_radix(&digits, radix) else {
+        return Err("number out of range");
+    };
+
+    // decimal numbers must fit in a signed cell, other bases may set all 64 bits
+    let limit = match (negative, radix) {
+        (true, _) => 1 << 63,
+        (false, 10) => i64::MAX as u64,
+        (false, _) => u64::MAX,
+    };
+    if magnitude > limit {
+        return Err("number out of range");
+    }
+    if negative {
+        Ok((magnitude as i64).wrapping_neg())
+    } else {
+        Ok(magnitude as i64)
+    }
+}
+
+fn resolve_include(program_path: &str, include_name: &str, include_paths: &[String]) -> Option<String> {
+    // files are searched next to the including file first, then in every -I directory
+    let program_dir = Path::new(program_path).parent().unwrap_or(Path::new(""));
+    let candidates = [program_dir].into_iter().chain(include_paths.iter().map(Path::new));
+    candidates
+        .map(|dir| dir.join(include_name))
+        .find(|path| path.is_file())
+        .and_then(|path| path.to_str().map(|path| path.to_owned()))
+}
+
+pub(crate) fn lex_file(
+    program_path: &str,
+    include_paths: &[String],
+    include_chain: &mut Vec<String>,
+    included: &mut HashSet<PathBuf>,
+    errors: &mut Vec<Diagnostic>,
+) -> Vec<Token> {
+    let Ok(source) = fs::read_to_string(program_path) else {
+        errors.push(Diagnostic::error("io", None, format!("unable to read file {program_path}")));
+        return Vec::new();
+    };
+    if source.is_empty() {
+        errors.push(Diagnostic::error("io", None, format!("no lines in file {program_path}")));
+        return Vec::new();
+    }
+    include_chain.push(program_path.to_owned());
+    included.extend(fs::canonicalize(program_path).ok());
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut name_loc: Option<(&str, usize, usize)> = None;
+    let mut include_loc: Option<(usize, usize)> = None;
+    // on an error the offending word is skipped and lexing goes on with the next one
+    for (word, row_no, col_no) in find_words(program_path, &source, errors) {
+        let word_len = word.chars().count();
+        if let Some((include_row, include_col)) = include_loc.take() {
+            if !word.starts_with('"') {
+                let msg = "expected file path string after 'include'";
+                errors.push(span_diagnostic(program_path, "include", include_row, include_col, "include".len(), msg));
+                continue;
+            }
+            let include_name = unescape(program_path, row_no, col_no, &word[1..word.len() - 1], errors);
+            let Some(include_path) = resolve_include(program_path, &include_name, include_paths) else {
+                let msg = format!("unable to find included file '{include_name}'");
+                errors.push(span_diagnostic(program_path, "include", row_no, col_no, word_len, &msg));
+                continue;
+            };
+            let canonical_path = fs::canonicalize(&include_path).ok();
+            if include_chain.iter().any(|path| fs::canonicalize(path).ok() == canonical_path) {
+                let msg = "include cycle detected";
+                let mut diagnostic = span_diagnostic(program_path, "include", include_row, include_col, "include".len(), msg);
+                let chain = include_chain.iter().chain([&include_path]);
+                for (path, included) in chain.clone().zip(chain.skip(1)) {
+                    diagnostic = diagnostic.with_note(None, format!("{path} includes {included}"));
+                }
+                errors.push(diagnostic);
+                continue;
+            }
+            // a file is only included once, even when several files include it
+            if canonical_path.as_ref().is_some_and(|path| included.contains(path)) {
+                continue;
+            }
+            tokens.extend(lex_file(&include_path, include_paths, include_chain, included, errors));
+            continue;
+        }
+        if word == "include" {
+            include_loc = Some((row_no, col_no));
+            continue;
+        }
+        if let Some((keyword, name_row, name_col)) = name_loc.take() {
+            // an invalid name is still defined so its body does not cause more errors
+            if is_reserved(&word) {
+                let msg = format!("cannot redefine builtin word '{word}'");
+                errors.push(span_diagnostic(program_path, "syntax", row_no, col_no, word_len, &msg));
+            } else if looks_like_number(&word) || word.starts_with(['"', '\'']) {
+                let msg = format!("'{word}' is not a valid word name");
+                errors.push(span_diagnostic(program_path, "syntax", row_no, col_no, word_len, &msg));
+            }
+            tokens.push(Token {
+                file_path: program_path.to_owned(),
+                row: name_row,
+                col: name_col,
+                len: keyword.len(),
+                word: match keyword {
+                    "const" => constant(word),
+                    "macro" => makro(word),
+                    _ => define(word, None),
+                },
+                expanded_from: Vec::new(),
+            });
+            continue;
+        }
+        if let Some(keyword) = [":", "const", "macro"].into_iter().find(|&keyword| keyword == word) {
+            name_loc = Some((keyword, row_no, col_no));
+            continue;
+        }
+
+        let word = match lex_word(&word) {
+            Some(word) => word,
+            None if word.starts_with('"') => {
+                push_string(unescape(program_path, row_no, col_no, &word[1..word.len() - 1], errors))
+            }
+            None if word.starts_with('\'') => {
+                let text = unescape(program_path, row_no, col_no, &word[1..word.len() - 1], errors);
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(char), None) => push(char as i64),
+                    _ => {
+                        let msg = format!("character literal {word} must contain exactly one character");
+                        errors.push(span_diagnostic(program_path, "syntax", row_no, col_no, word_len, &msg));
+                        continue;
+                    }
+                }
+            }
+            None if looks_like_number(&word) => match parse_number(&word) {
+                Ok(number) => push(number),
+                Err(err) => {
+                    let msg = format!("invalid number '{word}': {err}");
+                    errors.push(span_diagnostic(program_path, "syntax", row_no, col_no, word_len, &msg));
+                    continue;
+                }
+            },
+            None => call(word, None),
+        };
+        tokens.push(Token {
+            file_path: program_path.to_owned(),
+            row: row_no,
+            col: col_no,
+            len: word_len,
+            word,
+            expanded_from: Vec::new(),
+        });
+    }
+    if let Some((keyword, name_row, name_col)) = name_loc {
+        let msg = format!("expected name after '{keyword}'");
+        errors.push(span_diagnostic(program_path, "syntax", name_row, name_col, keyword.len(), &msg));
+    }
+    if let Some((include_row, include_col)) = include_loc {
+        let msg = "expected file path string after 'include'";
+        errors.push(span_diagnostic(program_path, "include", include_row, include_col, "include".len(), msg));
+    }
+    include_chain.pop();
+    tokens
+}
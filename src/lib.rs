@@ -0,0 +1,132 @@
+//! RustyForth as a library: lex and cross-reference a program, then run it in
+//! the [`Vm`] or compile it to assembly. Nothing here exits the process,
+//! every failure comes back as an [`Error`] holding the diagnostics.
+//!
+//! ```no_run
+//! let tokens = rustyforth::lex("hello.rf", &[])?;
+//! let program = rustyforth::crossreference(tokens)?;
+//! let mut output = Vec::new();
+//! let status = rustyforth::Vm::with_io(vec!["hello.rf".to_owned()], &b""[..], &mut output).run(&program)?;
+//! # Ok::<(), rustyforth::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+
+mod checker;
+mod codegen;
+mod constants;
+mod crossref;
+mod diagnostic;
+mod lexer;
+mod macros;
+mod vm;
+mod word;
+
+pub use codegen::compile_to_asm;
+pub use diagnostic::Diagnostic;
+pub use diagnostic::Severity;
+pub use diagnostic::Span;
+pub use lexer::Expansion;
+pub use lexer::Token;
+pub use vm::Vm;
+pub use word::DataType;
+pub use word::Word;
+
+use checker::StackEffect;
+
+const RETURN_STACK_CAPACITY: usize = 1_000_000;
+const MEM_CAPACITY: usize = 640_000;
+
+/// The diagnostics of a failed step, in the order they were found. Lexing and
+/// cross-referencing report every error they find, later steps the first one.
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl From<Diagnostic> for Error {
+    fn from(diagnostic: Diagnostic) -> Error {
+        Error {
+            diagnostics: vec![diagnostic],
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for diagnostic in &self.diagnostics {
+            write!(f, "{}", diagnostic.render())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn collected(errors: Vec<Diagnostic>) -> Result<(), Error> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(Error { diagnostics: errors })
+}
+
+fn lex_tokens(program_path: &str, include_paths: &[String], errors: &mut Vec<Diagnostic>) -> Vec<Token> {
+    let program = lexer::lex_file(program_path, include_paths, &mut Vec::new(), &mut HashSet::new(), errors);
+    let program = macros::expand_macros(program, errors);
+    constants::evaluate_constants(program, errors)
+}
+
+fn check_program(tokens: Vec<Token>) -> Result<Program, Error> {
+    let effects = checker::check_stack_effects(&tokens)?;
+    Ok(Program { tokens, effects })
+}
+
+/// Reads the file and the files it includes into tokens, with macros
+/// expanded and constants evaluated.
+pub fn lex(program_path: &str, include_paths: &[String]) -> Result<Vec<Token>, Error> {
+    let mut errors: Vec<Diagnostic> = Vec::new();
+    let tokens = lex_tokens(program_path, include_paths, &mut errors);
+    collected(errors)?;
+    Ok(tokens)
+}
+
+/// Resolves the blocks and calls of lexed tokens and checks the stack
+/// effects of the program.
+pub fn crossreference(tokens: Vec<Token>) -> Result<Program, Error> {
+    let mut errors: Vec<Diagnostic> = Vec::new();
+    let tokens = crossref::crossreference_blocks(tokens, &mut errors);
+    collected(errors)?;
+    check_program(tokens)
+}
+
+/// A cross-referenced program whose stack effects have been checked, ready
+/// to run in a [`Vm`] or to be compiled with [`compile_to_asm`].
+#[derive(Debug, Clone)]
+pub struct Program {
+    tokens: Vec<Token>,
+    // stack effect of every defined word by the index of its ':'
+    effects: HashMap<usize, Option<StackEffect>>,
+}
+
+impl Program {
+    /// Lexes and cross-references the file, reporting the errors of both
+    /// steps together.
+    pub fn load(program_path: &str, include_paths: &[String]) -> Result<Program, Error> {
+        let mut errors: Vec<Diagnostic> = Vec::new();
+        let tokens = lex_tokens(program_path, include_paths, &mut errors);
+        let tokens = crossref::crossreference_blocks(tokens, &mut errors);
+        collected(errors)?;
+        check_program(tokens)
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Checks that every word gets values of the types it works on.
+    pub fn typecheck(&self) -> Result<(), Error> {
+        Ok(checker::check_types(&self.tokens, &self.effects)?)
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::token_diagnostic;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Expansion;
+use crate::lexer::Token;
+use crate::word::Word;
+
+const MACRO_EXPANSION_LIMIT: usize = 100;
+const MACRO_TOKEN_LIMIT: usize = 100_000;
+
+// `expanded` counts every token taken from a macro body for one use of a
+// macro in the program, so a macro that calls itself more than once cannot
+// expand exponentially before the depth limit is reached
+fn expand_macro(
+    site: &Token,
+    name: &str,
+    macros: &HashMap<String, Vec<Token>>,
+    out_program: &mut Vec<Token>,
+    expanded: &mut usize,
+) -> Result<(), Diagnostic> {
+    if site.expanded_from.len() >= MACRO_EXPANSION_LIMIT {
+        let msg = format!("macro expansion limit of {MACRO_EXPANSION_LIMIT} reached while expanding '{name}'");
+        return Err(token_diagnostic(site, "macro", &msg));
+    }
+    let expansion = Expansion {
+        macro_name: name.to_owned(),
+        span: site.span(),
+    };
+    for token in &macros[name] {
+        *expanded += 1;
+        if *expanded > MACRO_TOKEN_LIMIT {
+            let msg = format!("macros expand to more than {MACRO_TOKEN_LIMIT} tokens while expanding '{name}'");
+            return Err(token_diagnostic(site, "macro", &msg));
+        }
+        let expanded_token = Token {
+            expanded_from: [expansion.clone()]
+                .into_iter()
+                .chain(site.expanded_from.iter().cloned())
+                .collect(),
+            ..token.clone()
+        };
+        match &token.word {
+            Word::OpCall(inner_name, _) if macros.contains_key(inner_name) => {
+                expand_macro(&expanded_token, inner_name, macros, out_program, expanded)?;
+            }
+            _ => out_program.push(expanded_token),
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn expand_macros(program: Vec<Token>, errors: &mut Vec<Diagnostic>) -> Vec<Token> {
+    // macros are collected first so they can be used before their definition
+    let mut macros: HashMap<String, Vec<Token>> = HashMap::new();
+    let mut macro_tokens: HashMap<String, &Token> = HashMap::new();
+    let mut rest: Vec<&Token> = Vec::new();
+    let mut token_idx = 0;
+    while token_idx < program.len() {
+        let token = &program[token_idx];
+        let Word::OpMacro(name) = &token.word else {
+            rest.push(token);
+            token_idx += 1;
+            continue;
+        };
+        // a macro defined twice keeps its first definition
+        let duplicate = macro_tokens.get(name).copied();
+        if let Some(prev) = duplicate {
+            let msg = format!(
+                "macro '{name}' is already defined at {}:{}:{}",
+                prev.file_path, prev.row, prev.col
+            );
+            errors.push(token_diagnostic(token, "macro", &msg));
+        }
+
+        // blocks inside the body are closed by their own 'end'
+        let mut depth = 0;
+        let mut body: Vec<Token> = Vec::new();
+        loop {
+            token_idx += 1;
+            let Some(body_token) = program.get(token_idx) else {
+                errors.push(token_diagnostic(token, "macro", "'macro' is missing its 'end'"));
+                break;
+            };
+            match body_token.word {
+                Word::OpMacro(_) => {
+                    errors.push(token_diagnostic(body_token, "macro", "macros cannot be defined inside other macros"));
+                    continue;
+                }
+                Word::OpIf(_) | Word::OpWhile | Word::OpConst(_) => depth += 1,
+                Word::OpEnd(_) if depth == 0 => break,
+                Word::OpEnd(_) => depth -= 1,
+                _ => (),
+            }
+            body.push(body_token.clone());
+        }
+        if duplicate.is_none() {
+            macro_tokens.insert(name.to_owned(), token);
+            macros.insert(name.to_owned(), body);
+        }
+        token_idx += 1;
+    }
+
+    let mut out_program: Vec<Token> = Vec::new();
+    let mut failed = false;
+    for token in rest {
+        match &token.word {
+            Word::OpDefine(name, _) | Word::OpConst(name) if macros.contains_key(name) => {
+                let prev = macro_tokens[name];
+                let msg = format!(
+                    "'{name}' is already defined as a macro at {}:{}:{}",
+                    prev.file_path, prev.row, prev.col
+                );
+                errors.push(token_diagnostic(token, "macro", &msg));
+                out_program.push(token.clone());
+            }
+            // after the first failed expansion no more macros are expanded, the
+            // partial expansion is dropped so its blocks cause no more errors
+            Word::OpCall(name, _) if macros.contains_key(name) => {
+                if failed {
+                    continue;
+                }
+                let start = out_program.len();
+                let mut expanded = 0;
+                if let Err(diagnostic) = expand_macro(token, name, &macros, &mut out_program, &mut expanded) {
+                    errors.push(diagnostic);
+                    out_program.truncate(start);
+                    failed = true;
+                }
+            }
+            _ => out_program.push(token.clone()),
+        }
+    }
+    out_program
+}
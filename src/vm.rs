@@ -0,0 +1,571 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::diagnostic::token_diagnostic;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Token;
+use crate::word::Word;
+use crate::Error;
+use crate::Program;
+use crate::MEM_CAPACITY;
+use crate::RETURN_STACK_CAPACITY;
+
+// Linux x86-64 syscalls and constants emulated by the simulator
+const SYS_READ: i64 = 0;
+const SYS_WRITE: i64 = 1;
+const SYS_OPEN: i64 = 2;
+const SYS_CLOSE: i64 = 3;
+const SYS_EXIT: i64 = 60;
+const SYS_EXIT_GROUP: i64 = 231;
+const O_ACCMODE: i64 = 0o3;
+const O_RDONLY: i64 = 0o0;
+const O_WRONLY: i64 = 0o1;
+const O_CREAT: i64 = 0o100;
+const O_TRUNC: i64 = 0o1000;
+const O_APPEND: i64 = 0o2000;
+const EIO: i32 = 5;
+const EBADF: i64 = 9;
+const EFAULT: i64 = 14;
+
+fn handle_stack_empty<T>(value_in_stack: Option<T>, token: &Token) -> Result<T, Diagnostic> {
+    value_in_stack.ok_or_else(|| token_diagnostic(token, "runtime", "stack is empty"))
+}
+
+fn handle_division_by_zero(divisor: i64, token: &Token) -> Result<(), Diagnostic> {
+    if divisor == 0 {
+        return Err(token_diagnostic(token, "runtime", "division by zero"));
+    }
+    Ok(())
+}
+
+fn memory_range(memory: &[u8], addr: i64, len: i64) -> Option<Range<usize>> {
+    let start = usize::try_from(addr).ok().filter(|&start| start > 0)?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    (end <= memory.len()).then_some(start..end)
+}
+
+fn handle_memory_range(memory: &[u8], addr: i64, len: i64, token: &Token) -> Result<Range<usize>, Diagnostic> {
+    memory_range(memory, addr, len).ok_or_else(|| {
+        let msg = format!("invalid memory access of {} bytes at address {}", len, addr);
+        token_diagnostic(token, "runtime", &msg)
+    })
+}
+
+fn errno(err: io::Error) -> i64 {
+    -(err.raw_os_error().unwrap_or(EIO) as i64)
+}
+
+fn push_string_array<'a>(memory: &mut Vec<u8>, strings: impl Iterator<Item = &'a [u8]>) -> usize {
+    let mut addrs: Vec<usize> = Vec::new();
+    for string in strings {
+        addrs.push(memory.len());
+        memory.extend_from_slice(string);
+        memory.push(0);
+    }
+    let array_addr = memory.len();
+    for addr in addrs.into_iter().chain([0]) {
+        memory.extend_from_slice(&(addr as i64).to_le_bytes());
+    }
+    array_addr
+}
+
+/// Simulates programs. What a program prints goes to the output and 'key',
+/// 'accept' and reads of file descriptor 0 take bytes from the input, so a
+/// program can run against buffers instead of the process' stdin and stdout.
+pub struct Vm<'io> {
+    args: Vec<String>,
+    input: Box<dyn Read + 'io>,
+    output: Box<dyn Write + 'io>,
+}
+
+impl Vm<'static> {
+    /// A VM reading stdin and writing stdout. `args` are what the program
+    /// sees through 'argc' and 'argv', starting with the program name.
+    pub fn new(args: Vec<String>) -> Vm<'static> {
+        Vm::with_io(args, io::stdin(), io::stdout())
+    }
+}
+
+impl<'io> Vm<'io> {
+    /// A VM reading `input` and writing `output`, e.g. byte slices and a
+    /// `Vec<u8>` to run a program on fixed input and capture what it prints.
+    pub fn with_io(args: Vec<String>, input: impl Read + 'io, output: impl Write + 'io) -> Vm<'io> {
+        Vm {
+            args,
+            input: Box::new(input),
+            output: Box::new(output),
+        }
+    }
+
+    /// Runs the program until it ends or calls 'exit', returning its exit
+    /// status. Runtime errors like a division by zero are returned as errors.
+    pub fn run(&mut self, program: &Program) -> Result<i32, Error> {
+        let status = self.execute(program);
+        // output the program printed before a runtime error should not be lost
+        let flushed = self.output.flush();
+        let status = status?;
+        if let Err(err) = flushed {
+            return Err(Diagnostic::error("io", None, format!("unable to flush output: {err}")).into());
+        }
+        Ok(status)
+    }
+
+    fn write_output(&mut self, bytes: &[u8], token: &Token) -> Result<(), Diagnostic> {
+        self.output
+            .write_all(bytes)
+            .map_err(|err| token_diagnostic(token, "io", &format!("unable to write output: {err}")))
+    }
+
+    // anything written so far should be visible before waiting for input
+    fn flush_output(&mut self, token: &Token) -> Result<(), Diagnostic> {
+        self.output
+            .flush()
+            .map_err(|err| token_diagnostic(token, "io", &format!("unable to flush output: {err}")))
+    }
+
+    fn read_byte(&mut self, token: &Token) -> Result<Option<u8>, Diagnostic> {
+        self.flush_output(token)?;
+        let mut byte = [0];
+        match self.input.read(&mut byte) {
+            Ok(1) => Ok(Some(byte[0])),
+            _ => Ok(None),
+        }
+    }
+
+    fn syscall(
+        &mut self,
+        number: i64,
+        args: &[i64],
+        memory: &mut [u8],
+        files: &mut HashMap<i64, File>,
+        token: &Token,
+    ) -> Result<i64, Diagnostic> {
+        let arg = |idx: usize| args.get(idx).copied().unwrap_or(0);
+        match number {
+            SYS_READ => {
+                let Some(range) = memory_range(memory, arg(1), arg(2)) else { return Ok(-EFAULT) };
+                let result = match arg(0) {
+                    0 => {
+                        self.flush_output(token)?;
+                        self.input.read(&mut memory[range])
+                    }
+                    fd => match files.get_mut(&fd) {
+                        Some(file) => file.read(&mut memory[range]),
+                        None => return Ok(-EBADF),
+                    },
+                };
+                Ok(result.map_or_else(errno, |count| count as i64))
+            }
+            SYS_WRITE => {
+                let Some(range) = memory_range(memory, arg(1), arg(2)) else { return Ok(-EFAULT) };
+                let result = match arg(0) {
+                    1 => self.output.write_all(&memory[range.clone()]),
+                    2 => {
+                        self.flush_output(token)?;
+                        io::stderr().write_all(&memory[range.clone()])
+                    }
+                    fd => match files.get_mut(&fd) {
+                        Some(file) => file.write_all(&memory[range.clone()]),
+                        None => return Ok(-EBADF),
+                    },
+                };
+                Ok(result.map_or_else(errno, |_| range.len() as i64))
+            }
+            SYS_OPEN => {
+                let Some(path_range) = memory_range(memory, arg(0), 1) else { return Ok(-EFAULT) };
+                let path_len = memory[path_range.start..].iter().position(|&byte| byte == 0);
+                let Some(path_len) = path_len else { return Ok(-EFAULT) };
+                let path = String::from_utf8_lossy(&memory[path_range.start..path_range.start + path_len]);
+                let (flags, mode) = (arg(1), arg(2));
+                let result = File::options()
+                    .read(flags & O_ACCMODE != O_WRONLY)
+                    .write(flags & O_ACCMODE != O_RDONLY)
+                    .append(flags & O_APPEND != 0)
+                    .create(flags & O_CREAT != 0)
+                    .truncate(flags & O_TRUNC != 0)
+                    .mode(mode as u32)
+                    .open(path.as_ref());
+                match result {
+                    Ok(file) => {
+                        let fd = (3..).find(|fd| !files.contains_key(fd)).unwrap_or(3);
+                        files.insert(fd, file);
+                        Ok(fd)
+                    }
+                    Err(err) => Ok(errno(err)),
+                }
+            }
+            SYS_CLOSE => match files.remove(&arg(0)) {
+                Some(_) => Ok(0),
+                None => Ok(-EBADF),
+            },
+            _ => {
+                let msg = format!("syscall {} is not supported in simulation mode", number);
+                Err(token_diagnostic(token, "runtime", &msg))
+            }
+        }
+    }
+
+    fn execute(&mut self, program: &Program) -> Result<i32, Error> {
+        let program = &program.tokens;
+        let mut stack: Vec<i64> = Vec::new();
+        let mut return_stack: Vec<usize> = Vec::new();
+
+        // address 0 is kept unused as null, followed by the string literals (each
+        // one terminated by a NUL byte) and then the `mem` buffer
+        let mut memory: Vec<u8> = vec![0];
+        let mut string_addrs: HashMap<usize, usize> = HashMap::new();
+        for (token_idx, token) in program.iter().enumerate() {
+            if let Word::OpPushStr(text) = &token.word {
+                string_addrs.insert(token_idx, memory.len());
+                memory.extend_from_slice(text.as_bytes());
+                memory.push(0);
+            }
+        }
+
+        // arguments and environment are laid out as on the process stack, NUL
+        // terminated strings pointed to by NULL terminated arrays of addresses
+        let argv_addr = push_string_array(&mut memory, self.args.iter().map(|arg| arg.as_bytes()));
+        let vars: Vec<Vec<u8>> = env::vars_os()
+            .map(|(key, value)| [key.as_bytes(), b"=", value.as_bytes()].concat())
+            .collect();
+        let envp_addr = push_string_array(&mut memory, vars.iter().map(|var| var.as_slice()));
+
+        let mem_addr = memory.len();
+        memory.resize(mem_addr + MEM_CAPACITY, 0);
+        let mut files: HashMap<i64, File> = HashMap::new();
+
+        let mut token_idx = 0;
+        while token_idx < program.len() {
+            let token = &program[token_idx];
+            match program[token_idx].word {
+                Word::OpPush(num) => stack.push(num),
+                Word::OpPlus => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(a.wrapping_add(b));
+                }
+                Word::OpMinus => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b.wrapping_sub(a));
+                }
+                Word::OpEqual => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push((a == b) as i64);
+                }
+                Word::OpDump => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    self.write_output(format!("{a}\n").as_bytes(), token)?;
+                }
+                Word::OpDup => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(a);
+                    stack.push(a);
+                }
+                Word::OpGt => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push((a < b) as i64);
+                }
+                Word::OpLt => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push((b < a) as i64);
+                }
+                Word::OpLe => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push((b <= a) as i64);
+                }
+                Word::OpGe => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push((b >= a) as i64);
+                }
+                Word::OpNe => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push((b != a) as i64);
+                }
+                Word::OpZeroEqual => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push((a == 0) as i64);
+                }
+                Word::OpZeroLt => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push((a < 0) as i64);
+                }
+                Word::OpZeroGt => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push((a > 0) as i64);
+                }
+                Word::OpIf(else_end_idx) => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    if a == 0 {
+                        let Some(else_end_idx) = else_end_idx 
+                                else {return Err(token_diagnostic(token, "internal", "'if' does not have reference to end of block").into())};
+                        token_idx = else_end_idx - 1;
+                    }
+                }
+                Word::OpElse(end_idx) => {
+                    let Some(end_idx) = end_idx 
+                            else {return Err(token_diagnostic(token, "internal", "'else' does not have reference to end of block").into())};
+                    token_idx = end_idx - 1;
+                }
+                Word::OpEnd(wile_end_idx) => {
+                    let Some(wile_end_idx) = wile_end_idx 
+                            else {return Err(token_diagnostic(token, "internal", "'end' does not have reference to while block or next instruction").into())};
+                    token_idx = wile_end_idx - 1;
+                }
+                Word::OpWhile => (),
+                Word::OpDo(end_idx) => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    if a == 0 {
+                        let Some(end_idx) = end_idx
+                                else {return Err(token_diagnostic(token, "internal", "'do' does not have reference to end of block").into())};
+                        token_idx = end_idx - 1;
+                    }
+                }
+                Word::OpMul => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b.wrapping_mul(a));
+                }
+                Word::OpDiv => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    handle_division_by_zero(a, token)?;
+                    stack.push(b.wrapping_div(a));
+                }
+                Word::OpMod => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    handle_division_by_zero(a, token)?;
+                    stack.push(b.wrapping_rem(a));
+                }
+                Word::OpDivMod => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    handle_division_by_zero(a, token)?;
+                    stack.push(b.wrapping_rem(a));
+                    stack.push(b.wrapping_div(a));
+                }
+                Word::OpNegate => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(a.wrapping_neg());
+                }
+                Word::OpAbs => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(a.wrapping_abs());
+                }
+                Word::OpMin => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b.min(a));
+                }
+                Word::OpMax => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b.max(a));
+                }
+                Word::OpAnd => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b & a);
+                }
+                Word::OpOr => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b | a);
+                }
+                Word::OpXor => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b ^ a);
+                }
+                Word::OpNot => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(!a);
+                }
+                Word::OpShl => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b.wrapping_shl(a as u32));
+                }
+                Word::OpShr => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push((b as u64).wrapping_shr(a as u32) as i64);
+                }
+                Word::OpSar => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b.wrapping_shr(a as u32));
+                }
+                Word::OpSwap => {
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Word::OpDrop => {
+                    handle_stack_empty(stack.pop(), token)?;
+                }
+                Word::OpOver => {
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(a);
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Word::OpRot => {
+                    let c = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b);
+                    stack.push(c);
+                    stack.push(a);
+                }
+                Word::OpMinusRot => {
+                    let c = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(c);
+                    stack.push(a);
+                    stack.push(b);
+                }
+                Word::OpNip => {
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b);
+                }
+                Word::OpTuck => {
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(b);
+                    stack.push(a);
+                    stack.push(b);
+                }
+                Word::OpTwoDup => {
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(a);
+                    stack.push(b);
+                    stack.push(a);
+                    stack.push(b);
+                }
+                Word::OpTwoDrop => {
+                    handle_stack_empty(stack.pop(), token)?;
+                    handle_stack_empty(stack.pop(), token)?;
+                }
+                Word::OpTwoSwap => {
+                    let d = handle_stack_empty(stack.pop(), token)?;
+                    let c = handle_stack_empty(stack.pop(), token)?;
+                    let b = handle_stack_empty(stack.pop(), token)?;
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    stack.push(c);
+                    stack.push(d);
+                    stack.push(a);
+                    stack.push(b);
+                }
+                Word::OpPushStr(ref text) => {
+                    stack.push(text.len() as i64);
+                    stack.push(string_addrs[&token_idx] as i64);
+                }
+                Word::OpPuts => {
+                    let addr = handle_stack_empty(stack.pop(), token)?;
+                    let len = handle_stack_empty(stack.pop(), token)?;
+                    let range = handle_memory_range(&memory, addr, len, token)?;
+                    self.write_output(&memory[range], token)?;
+                }
+                Word::OpEmit => {
+                    let a = handle_stack_empty(stack.pop(), token)?;
+                    self.write_output(&[a as u8], token)?;
+                }
+                Word::OpKey => {
+                    stack.push(self.read_byte(token)?.map_or(-1, |byte| byte as i64));
+                }
+                Word::OpAccept => {
+                    let max_len = handle_stack_empty(stack.pop(), token)?;
+                    let addr = handle_stack_empty(stack.pop(), token)?;
+                    let range = handle_memory_range(&memory, addr, max_len.max(0), token)?;
+                    let mut len = 0;
+                    while len < range.len() {
+                        let Some(byte) = self.read_byte(token)? else { break };
+                        memory[range.start + len] = byte;
+                        if byte == b'\n' {
+                            break;
+                        }
+                        len += 1;
+                    }
+                    stack.push(len as i64);
+                }
+                Word::OpMem => stack.push(mem_addr as i64),
+                Word::OpLoad(width) => {
+                    let addr = handle_stack_empty(stack.pop(), token)?;
+                    let range = handle_memory_range(&memory, addr, width as i64, token)?;
+                    let mut bytes = [0; 8];
+                    bytes[..width].copy_from_slice(&memory[range]);
+                    stack.push(i64::from_le_bytes(bytes));
+                }
+                Word::OpStore(width) => {
+                    let addr = handle_stack_empty(stack.pop(), token)?;
+                    let value = handle_stack_empty(stack.pop(), token)?;
+                    let range = handle_memory_range(&memory, addr, width as i64, token)?;
+                    memory[range].copy_from_slice(&value.to_le_bytes()[..width]);
+                }
+                Word::OpSyscall(arg_count) => {
+                    let number = handle_stack_empty(stack.pop(), token)?;
+                    let args: Vec<i64> = (0..arg_count)
+                        .map(|_| handle_stack_empty(stack.pop(), token))
+                        .collect::<Result<_, _>>()?;
+                    if number == SYS_EXIT || number == SYS_EXIT_GROUP {
+                        return Ok(args.first().copied().unwrap_or(0) as i32);
+                    }
+                    stack.push(self.syscall(number, &args, &mut memory, &mut files, token)?);
+                }
+                Word::OpExit => {
+                    let status = handle_stack_empty(stack.pop(), token)?;
+                    return Ok(status as i32);
+                }
+                Word::OpArgc => stack.push(self.args.len() as i64),
+                Word::OpArgv => stack.push(argv_addr as i64),
+                Word::OpEnvp => stack.push(envp_addr as i64),
+                Word::OpCast(_) => (),
+                Word::OpConst(_) => {
+                    return Err(token_diagnostic(token, "internal", "'const' should have been evaluated before simulation").into());
+                }
+                Word::OpMacro(_) => {
+                    return Err(token_diagnostic(token, "internal", "'macro' should have been expanded before simulation").into());
+                }
+                Word::OpDefine(_, after_end_idx) => {
+                    let Some(after_end_idx) = after_end_idx
+                            else {return Err(token_diagnostic(token, "internal", "':' does not have reference to end of definition").into())};
+                    token_idx = after_end_idx - 1;
+                }
+                Word::OpCall(_, define_idx) => {
+                    let Some(define_idx) = define_idx
+                            else {return Err(token_diagnostic(token, "internal", "call does not have reference to definition").into())};
+                    if return_stack.len() >= RETURN_STACK_CAPACITY {
+                        return Err(token_diagnostic(token, "runtime", "return stack overflow").into());
+                    }
+                    return_stack.push(token_idx);
+                    token_idx = define_idx;
+                }
+                Word::OpReturn => {
+                    token_idx = handle_stack_empty(return_stack.pop(), token)?;
+                }
+            }
+            token_idx += 1;
+        }
+        Ok(0)
+    }
+}
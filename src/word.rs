@@ -0,0 +1,341 @@
+use std::fmt;
+
+// Type of a value on the stack as seen by the type checker. Values that come
+// into a defined word from its caller are Any and satisfy every type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataType {
+    Int,
+    Bool,
+    Ptr,
+    Any,
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataType::Int => write!(f, "int"),
+            DataType::Bool => write!(f, "bool"),
+            DataType::Ptr => write!(f, "ptr"),
+            DataType::Any => write!(f, "any"),
+        }
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Word {
+    OpPush(i64),
+    OpPlus,
+    OpMinus,
+    OpEqual,
+    OpDump,
+    OpDup,
+    OpGt,
+    OpLt,
+    OpLe,
+    OpGe,
+    OpNe,
+    OpZeroEqual,
+    OpZeroLt,
+    OpZeroGt,
+    OpIf(Option<usize>),
+    OpEnd(Option<usize>),
+    OpElse(Option<usize>),
+    OpWhile,
+    OpDo(Option<usize>),
+    OpMul,
+    OpDiv,
+    OpMod,
+    OpDivMod,
+    OpNegate,
+    OpAbs,
+    OpMin,
+    OpMax,
+    OpAnd,
+    OpOr,
+    OpXor,
+    OpNot,
+    OpShl,
+    OpShr,
+    OpSar,
+    OpSwap,
+    OpDrop,
+    OpOver,
+    OpRot,
+    OpMinusRot,
+    OpNip,
+    OpTuck,
+    OpTwoDup,
+    OpTwoDrop,
+    OpTwoSwap,
+    OpPushStr(String),
+    OpPuts,
+    OpEmit,
+    OpKey,
+    OpAccept,
+    OpMem,
+    OpLoad(usize),
+    OpStore(usize),
+    OpSyscall(usize),
+    OpExit,
+    OpArgc,
+    OpArgv,
+    OpEnvp,
+    OpCast(DataType),
+    OpConst(String),
+    OpMacro(String),
+    OpDefine(String, Option<usize>),
+    OpCall(String, Option<usize>),
+    OpReturn,
+}
+
+pub(crate) fn push(num: i64) -> Word {
+    Word::OpPush(num)
+}
+pub(crate) fn plus() -> Word {
+    Word::OpPlus
+}
+
+pub(crate) fn minus() -> Word {
+    Word::OpMinus
+}
+
+pub(crate) fn equal() -> Word {
+    Word::OpEqual
+}
+
+pub(crate) fn dump() -> Word {
+    Word::OpDump
+}
+
+pub(crate) fn dup() -> Word {
+    Word::OpDup
+}
+
+pub(crate) fn gt() -> Word {
+    Word::OpGt
+}
+
+pub(crate) fn lt() -> Word {
+    Word::OpLt
+}
+
+pub(crate) fn le() -> Word {
+    Word::OpLe
+}
+
+pub(crate) fn ge() -> Word {
+    Word::OpGe
+}
+
+pub(crate) fn ne() -> Word {
+    Word::OpNe
+}
+
+pub(crate) fn zero_equal() -> Word {
+    Word::OpZeroEqual
+}
+
+pub(crate) fn zero_lt() -> Word {
+    Word::OpZeroLt
+}
+
+pub(crate) fn zero_gt() -> Word {
+    Word::OpZeroGt
+}
+
+pub(crate) fn iff(else_end_idx: Option<usize>) -> Word {
+    Word::OpIf(else_end_idx)
+}
+
+pub(crate) fn elze(end_idx: Option<usize>) -> Word {
+    Word::OpElse(end_idx)
+}
+
+pub(crate) fn end(wile_end_idx: Option<usize>) -> Word {
+    Word::OpEnd(wile_end_idx)
+}
+
+pub(crate) fn wile() -> Word {
+    Word::OpWhile
+}
+
+pub(crate) fn doo(wile_end_idx: Option<usize>) -> Word {
+    Word::OpDo(wile_end_idx)
+}
+
+pub(crate) fn mul() -> Word {
+    Word::OpMul
+}
+
+pub(crate) fn div() -> Word {
+    Word::OpDiv
+}
+
+pub(crate) fn modd() -> Word {
+    Word::OpMod
+}
+
+pub(crate) fn divmod() -> Word {
+    Word::OpDivMod
+}
+
+pub(crate) fn negate() -> Word {
+    Word::OpNegate
+}
+
+pub(crate) fn abs() -> Word {
+    Word::OpAbs
+}
+
+pub(crate) fn min() -> Word {
+    Word::OpMin
+}
+
+pub(crate) fn max() -> Word {
+    Word::OpMax
+}
+
+pub(crate) fn and() -> Word {
+    Word::OpAnd
+}
+
+pub(crate) fn or() -> Word {
+    Word::OpOr
+}
+
+pub(crate) fn xor() -> Word {
+    Word::OpXor
+}
+
+pub(crate) fn not() -> Word {
+    Word::OpNot
+}
+
+pub(crate) fn shl() -> Word {
+    Word::OpShl
+}
+
+pub(crate) fn shr() -> Word {
+    Word::OpShr
+}
+
+pub(crate) fn sar() -> Word {
+    Word::OpSar
+}
+
+pub(crate) fn swap() -> Word {
+    Word::OpSwap
+}
+
+pub(crate) fn dropp() -> Word {
+    Word::OpDrop
+}
+
+pub(crate) fn over() -> Word {
+    Word::OpOver
+}
+
+pub(crate) fn rot() -> Word {
+    Word::OpRot
+}
+
+pub(crate) fn minus_rot() -> Word {
+    Word::OpMinusRot
+}
+
+pub(crate) fn nip() -> Word {
+    Word::OpNip
+}
+
+pub(crate) fn tuck() -> Word {
+    Word::OpTuck
+}
+
+pub(crate) fn two_dup() -> Word {
+    Word::OpTwoDup
+}
+
+pub(crate) fn two_drop() -> Word {
+    Word::OpTwoDrop
+}
+
+pub(crate) fn two_swap() -> Word {
+    Word::OpTwoSwap
+}
+
+pub(crate) fn push_string(text: String) -> Word {
+    Word::OpPushStr(text)
+}
+
+pub(crate) fn puts() -> Word {
+    Word::OpPuts
+}
+
+pub(crate) fn emit() -> Word {
+    Word::OpEmit
+}
+
+pub(crate) fn key() -> Word {
+    Word::OpKey
+}
+
+pub(crate) fn accept() -> Word {
+    Word::OpAccept
+}
+
+pub(crate) fn mem() -> Word {
+    Word::OpMem
+}
+
+pub(crate) fn load(width: usize) -> Word {
+    Word::OpLoad(width)
+}
+
+pub(crate) fn store(width: usize) -> Word {
+    Word::OpStore(width)
+}
+
+pub(crate) fn syscall(arg_count: usize) -> Word {
+    Word::OpSyscall(arg_count)
+}
+
+pub(crate) fn exitt() -> Word {
+    Word::OpExit
+}
+
+pub(crate) fn argc() -> Word {
+    Word::OpArgc
+}
+
+pub(crate) fn argv() -> Word {
+    Word::OpArgv
+}
+
+pub(crate) fn envp() -> Word {
+    Word::OpEnvp
+}
+
+pub(crate) fn cast(data_type: DataType) -> Word {
+    Word::OpCast(data_type)
+}
+
+pub(crate) fn constant(name: String) -> Word {
+    Word::OpConst(name)
+}
+
+pub(crate) fn makro(name: String) -> Word {
+    Word::OpMacro(name)
+}
+
+pub(crate) fn define(name: String, after_end_idx: Option<usize>) -> Word {
+    Word::OpDefine(name, after_end_idx)
+}
+
+pub(crate) fn call(name: String, define_idx: Option<usize>) -> Word {
+    Word::OpCall(name, define_idx)
+}
+
+pub(crate) fn ret() -> Word {
+    Word::OpReturn
+}
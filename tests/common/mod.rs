@@ -0,0 +1,33 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A directory for the files of one test, removed with everything in it when
+/// the test is done, whether it passed or not.
+pub struct WorkDir(PathBuf);
+
+impl Deref for WorkDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for WorkDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+pub fn workdir(name: &str) -> WorkDir {
+    let dir = std::env::temp_dir().join(format!("rustyforth-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).expect("unable to create test directory");
+    WorkDir(dir)
+}
@@ -0,0 +1,35 @@
+use std::fs;
+use std::process::Command;
+
+mod common;
+
+use common::workdir;
+
+const RUSTYFORTH: &str = env!("CARGO_BIN_EXE_rustyforth");
+
+// simulates `source` and returns its stdout and exit status
+fn simulate(name: &str, source: &str) -> (String, Option<i32>) {
+    let dir = workdir(name);
+    let file = format!("{name}.rf");
+    fs::write(dir.join(&file), source).expect("unable to write program");
+    let output = Command::new(RUSTYFORTH)
+        .current_dir(&dir)
+        .args(["sim", &file])
+        .output()
+        .expect("unable to run simulator");
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        output.status.code(),
+    )
+}
+
+#[test]
+fn deep_recursion() {
+    assert_eq!(
+        simulate("definitions_deep_recursion", ": down dup 0 > if 1 - down end ;\n4000 down .\n"),
+        ("0\n".to_owned(), Some(0))
+    );
+    // unbounded recursion stops with an error instead of growing forever
+    let (_, status) = simulate("definitions_return_stack_overflow", ": forever forever ;\nforever\n");
+    assert_eq!(status, Some(1));
+}
@@ -0,0 +1,113 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::rc::Rc;
+
+use rustyforth::Program;
+use rustyforth::Vm;
+
+mod common;
+
+use common::workdir;
+use common::WorkDir;
+
+fn write_program(dir: &WorkDir, name: &str, source: &str) -> String {
+    let path = dir.join(format!("{name}.rf"));
+    fs::write(&path, source).expect("unable to write program");
+    path.to_str().unwrap().to_owned()
+}
+
+fn run(program: &Program, input: &[u8]) -> (String, i32) {
+    let mut output: Vec<u8> = Vec::new();
+    let status = Vm::with_io(vec!["prog".to_owned()], input, &mut output)
+        .run(program)
+        .expect("program failed");
+    (String::from_utf8(output).unwrap(), status)
+}
+
+#[test]
+fn run_with_captured_io() {
+    let dir = workdir("embed-captured_io");
+    let path = write_program(
+        &dir,
+        "captured_io",
+        ": square dup * ;\n7 square .\n\"hi\\n\" puts\nkey emit key emit\nargc 3 + exit\n",
+    );
+    let tokens = rustyforth::lex(&path, &[]).expect("lexing failed");
+    let program = rustyforth::crossreference(tokens).expect("cross-referencing failed");
+    assert_eq!(run(&program, b"ok"), ("49\nhi\nok".to_owned(), 4));
+    // the same program can run again on other input
+    assert_eq!(run(&program, b"yo"), ("49\nhi\nyo".to_owned(), 4));
+}
+
+// output that only shows up in the log when flushed, and input that marks in
+// the same log when it is read
+struct BufferedOutput(Vec<u8>, Rc<RefCell<Vec<u8>>>);
+struct LoggedInput(&'static [u8], Rc<RefCell<Vec<u8>>>);
+
+impl Write for BufferedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.1.borrow_mut().append(&mut self.0);
+        Ok(())
+    }
+}
+
+impl Read for LoggedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.1.borrow_mut().extend_from_slice(b"<read>");
+        self.0.read(buf)
+    }
+}
+
+#[test]
+fn output_flushed_before_reading() {
+    let dir = workdir("embed-flush_before_read");
+    let path = write_program(&dir, "flush_before_read", "\"> \" puts 8 mem 0 0 syscall3 mem puts\n");
+    let program = Program::load(&path, &[]).expect("loading failed");
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let input = LoggedInput(b"hi", log.clone());
+    let output = BufferedOutput(Vec::new(), log.clone());
+    let status = Vm::with_io(Vec::new(), input, output).run(&program).expect("program failed");
+    assert_eq!(status, 0);
+    assert_eq!(String::from_utf8(log.take()).unwrap(), "> <read>hi");
+}
+
+#[test]
+fn compile_to_asm() {
+    let dir = workdir("embed-asm");
+    let path = write_program(&dir, "asm", "1 2 + .\n");
+    let program = Program::load(&path, &[]).expect("loading failed");
+    let asm = rustyforth::compile_to_asm(&program).expect("compilation failed");
+    assert!(asm.starts_with("BITS 64\n"));
+    assert!(asm.contains("    ;; -- plus --\n"));
+}
+
+#[test]
+fn errors_are_returned() {
+    let dir = workdir("embed-errors");
+    let path = write_program(&dir, "errors", "1 0xZZ .\nbar\n");
+    let err = Program::load(&path, &[]).unwrap_err();
+    let messages: Vec<&str> = err.diagnostics.iter().map(|diagnostic| diagnostic.message.as_str()).collect();
+    assert_eq!(messages, ["invalid number '0xZZ': invalid digit", "unknown word 'bar'"]);
+    assert_eq!(err.diagnostics[1].code, "unknown-word");
+
+    let path = write_program(&dir, "runtime_error", "1 . 1 0 /\n");
+    let program = Program::load(&path, &[]).expect("loading failed");
+    let mut output: Vec<u8> = Vec::new();
+    let err = Vm::with_io(Vec::new(), &b""[..], &mut output).run(&program).unwrap_err();
+    assert_eq!(err.diagnostics[0].message, "division by zero");
+    let span = err.diagnostics[0].span.as_ref().unwrap();
+    assert_eq!((span.row, span.col), (1, 9));
+    assert_eq!(output, b"1\n");
+
+    let path = write_program(&dir, "type_error", "1 2 = 3 +\n");
+    let program = Program::load(&path, &[]).expect("loading failed");
+    assert_eq!(program.typecheck().unwrap_err().diagnostics[0].code, "type");
+}
@@ -0,0 +1,525 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+mod common;
+
+use common::workdir;
+
+const RUSTYFORTH: &str = env!("CARGO_BIN_EXE_rustyforth");
+
+/// Whether the compiled programs can be checked. Without nasm and ld the
+/// comparison tests fail, unless RUSTYFORTH_SIM_ONLY=1 explicitly asks to only
+/// check simulation mode.
+fn have_toolchain() -> bool {
+    let found = Command::new("nasm").arg("-v").output().is_ok() && Command::new("ld").arg("-v").output().is_ok();
+    if !found && std::env::var_os("RUSTYFORTH_SIM_ONLY").is_none() {
+        panic!("nasm and ld are needed to compare with compiled programs, set RUSTYFORTH_SIM_ONLY=1 to only check simulation");
+    }
+    found
+}
+
+// runs the command with `input` written to its stdin
+fn run_with_input(command: &mut Command, input: &[u8]) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("unable to run program");
+    // a program may exit without reading everything, its stdin is then closed
+    let _ = child.stdin.take().unwrap().write_all(input);
+    child.wait_with_output().expect("unable to run program")
+}
+
+fn simulate(dir: &Path, options: &[&str], file: &str, args: &[&str], input: &[u8]) -> (String, Option<i32>) {
+    let output = run_with_input(
+        Command::new(RUSTYFORTH).current_dir(dir).args(options).args(["sim", file]).args(args),
+        input,
+    );
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        output.status.code(),
+    )
+}
+
+fn compile_and_run(dir: &Path, name: &str, file: &str, args: &[&str], input: &[u8]) -> (String, Option<i32>) {
+    let output = Command::new(RUSTYFORTH)
+        .current_dir(dir)
+        .args(["com", file])
+        .output()
+        .expect("unable to run compiler");
+    assert!(output.status.success(), "com failed: {:?}", output);
+    let output = run_with_input(Command::new(dir.join(name)).current_dir(dir).args(args), input);
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        output.status.code(),
+    )
+}
+
+/// Runs `source` in simulation mode with `args` and `input` on stdin and
+/// checks its output and exit status, then, if nasm and ld are available,
+/// compiles it and checks the compiled binary prints exactly the same thing
+/// and exits with the same status.
+fn check_modes_agree(name: &str, source: &str, args: &[&str], input: &[u8], expected: &str, status: i32) {
+    let dir = workdir(name);
+    let file = format!("{name}.rf");
+    fs::write(dir.join(&file), source).expect("unable to write program");
+
+    let sim = simulate(&dir, &[], &file, args, input);
+    assert_eq!(
+        sim,
+        (expected.to_owned(), Some(status)),
+        "unexpected simulation result"
+    );
+
+    if !have_toolchain() {
+        eprintln!("RUSTYFORTH_SIM_ONLY is set, not compiling {name}");
+        return;
+    }
+    let com = compile_and_run(&dir, name, &file, args, input);
+    assert_eq!(sim, com, "simulation and compiled program differ");
+}
+
+fn assert_modes_agree_with_status(name: &str, source: &str, expected: &str, status: i32) {
+    check_modes_agree(name, source, &[], b"", expected, status);
+}
+
+fn assert_modes_agree(name: &str, source: &str, expected: &str) {
+    assert_modes_agree_with_status(name, source, expected, 0);
+}
+
+fn compare(op: &str, a: i64, b: i64) -> bool {
+    match op {
+        "=" => a == b,
+        "!=" => a != b,
+        "<" => a < b,
+        "<=" => a <= b,
+        ">" => a > b,
+        ">=" => a >= b,
+        "0=" => a == 0,
+        "0<" => a < 0,
+        "0>" => a > 0,
+        _ => unreachable!("unknown comparison {op}"),
+    }
+}
+
+#[test]
+fn binary_comparisons() {
+    let mut source = String::new();
+    let mut expected = String::new();
+    for (a, b) in [(1, 2), (2, 2), (3, 2), (-1, 2), (2, -1), (-3, -3)] {
+        for op in ["=", "!=", "<", "<=", ">", ">="] {
+            source.push_str(&format!("{a} {b} {op} .\n"));
+            expected.push_str(&format!("{}\n", compare(op, a, b) as i64));
+        }
+    }
+    assert_modes_agree("binary_comparisons", &source, &expected);
+}
+
+#[test]
+fn zero_comparisons() {
+    let mut source = String::new();
+    let mut expected = String::new();
+    for a in [-5, 0, 5] {
+        for op in ["0=", "0<", "0>"] {
+            source.push_str(&format!("{a} {op} .\n"));
+            expected.push_str(&format!("{}\n", compare(op, a, 0) as i64));
+        }
+    }
+    assert_modes_agree("zero_comparisons", &source, &expected);
+}
+
+#[test]
+fn comparison_in_loop_condition() {
+    assert_modes_agree(
+        "comparison_in_loop_condition",
+        "0 while dup 5 < do dup . 1 + end .\n",
+        "0\n1\n2\n3\n4\n5\n",
+    );
+}
+
+#[test]
+fn sixty_four_bit_wrapping() {
+    assert_modes_agree(
+        "sixty_four_bit_wrapping",
+        "9223372036854775807 1 + .\n\
+         -9223372036854775808 1 - .\n\
+         4294967296 4294967296 * .\n\
+         -9223372036854775808 -1 / .\n\
+         -9223372036854775808 -1 mod .\n\
+         -9223372036854775808 negate .\n\
+         -9223372036854775808 abs .\n\
+         1 63 shl .\n\
+         -1 1 shr .\n\
+         -1 63 sar .\n\
+         -17 5 /mod . .\n",
+        "-9223372036854775808\n\
+         9223372036854775807\n\
+         0\n\
+         -9223372036854775808\n\
+         0\n\
+         -9223372036854775808\n\
+         -9223372036854775808\n\
+         -9223372036854775808\n\
+         9223372036854775807\n\
+         -1\n\
+         -3\n\
+         -2\n",
+    );
+}
+
+#[test]
+fn string_literals() {
+    assert_modes_agree(
+        "string_literals",
+        "\"Hello,\\tWorld!\\n\" puts\n\"\\\"quoted\\\" \\\\ \" type 42 .\n\"abc\" drop .\n",
+        "Hello,\tWorld!\n\"quoted\" \\ 42\n3\n",
+    );
+}
+
+#[test]
+fn numeric_literals() {
+    assert_modes_agree(
+        "numeric_literals",
+        "0xFF . 0b1010 . 0o755 . 1_000_000 . -5 . -0x10 . 0xFFFF_FFFF_FFFF_FFFF . +7 .\n",
+        "255\n10\n493\n1000000\n-5\n-16\n-1\n7\n",
+    );
+}
+
+#[test]
+fn exit_status() {
+    assert_modes_agree_with_status(
+        "exit_status",
+        ": fail \"failing\\n\" puts 3 exit ;\n1 . fail 2 .\n",
+        "1\nfailing\n",
+        3,
+    );
+    assert_modes_agree_with_status("exit_syscall", "42 60 syscall1\n", "", 42);
+}
+
+// runs a command with stdout and stderr written to the same file, to see the
+// order in which they were written
+fn run_interleaved(command: &mut Command, log: &Path) -> String {
+    let file = fs::File::create(log).expect("unable to create output file");
+    let status = command
+        .stdout(Stdio::from(file.try_clone().unwrap()))
+        .stderr(Stdio::from(file))
+        .status()
+        .expect("unable to run program");
+    assert!(status.success(), "program failed: {status}");
+    fs::read_to_string(log).unwrap()
+}
+
+#[test]
+fn stdout_flushed_before_stderr() {
+    let name = "stdout_before_stderr";
+    let dir = workdir(name);
+    let file = format!("{name}.rf");
+    fs::write(dir.join(&file), "\"out\" puts \"err\\n\" 2 1 syscall3 drop\n").expect("unable to write program");
+
+    let sim = run_interleaved(Command::new(RUSTYFORTH).current_dir(&dir).args(["sim", &file]), &dir.join("sim.log"));
+    assert_eq!(sim, "outerr\n");
+
+    if !have_toolchain() {
+        eprintln!("RUSTYFORTH_SIM_ONLY is set, not compiling {name}");
+        return;
+    }
+    let com = Command::new(RUSTYFORTH).current_dir(&dir).args(["com", &file]).output().expect("unable to run compiler");
+    assert!(com.status.success(), "com failed: {:?}", com);
+    let com = run_interleaved(Command::new(dir.join(name)).current_dir(&dir), &dir.join("com.log"));
+    assert_eq!(sim, com, "simulation and compiled program differ");
+}
+
+#[test]
+fn reading_input() {
+    // a line that fills the buffer is cut there, a negative size reads
+    // nothing and the newline is stored after the line without being counted
+    check_modes_agree(
+        "reading_input",
+        "key .\n\
+         mem 8 accept dup . mem puts '\\n' emit\n\
+         mem 2 + c@ .\n\
+         mem 3 accept dup . mem puts '\\n' emit\n\
+         mem -1 accept .\n\
+         mem 8 accept dup . mem puts '\\n' emit\n\
+         mem 8 accept . key .\n",
+        &[],
+        b"Xhi\nabcdef\n",
+        "88\n2\nhi\n10\n3\nabc\n0\n3\ndef\n0\n-1\n",
+        0,
+    );
+}
+
+#[test]
+fn program_arguments() {
+    // like examples/args.rf, argv[0] is left out as it is the path of the
+    // program in one mode and of the binary in the other
+    let source = format!(
+        "{}\n\
+         \"argc: \" puts argc .\n\
+         1 while dup argc < do dup 8 * argv + @ print-cstr 1 + end drop\n\
+         0 while dup 8 * envp + @ 0 != do 1 + end 0 > .\n",
+        include_str!("../examples/lib/cstr.rf")
+    );
+    check_modes_agree("program_arguments", &source, &["a", "b c"], b"", "argc: 3\na\nb c\n1\n", 0);
+}
+
+#[test]
+fn deep_recursion() {
+    assert_modes_agree("deep_recursion", ": down dup 0 > if 1 - down end ;\n5000 down .\n", "0\n");
+    // unbounded recursion is stopped before it overwrites anything
+    assert_modes_agree_with_status("return_stack_overflow", ": forever forever ;\nforever\n", "", 1);
+}
+
+#[test]
+fn include_once() {
+    let dir = workdir("include_once");
+    fs::write(dir.join("lib.rf"), ": sq dup * ;\n").expect("unable to write program");
+    fs::write(dir.join("a.rf"), "include \"lib.rf\"\n: a 2 sq ;\n").expect("unable to write program");
+    fs::write(dir.join("b.rf"), "include \"lib.rf\"\n: b 3 sq ;\n").expect("unable to write program");
+    fs::write(
+        dir.join("main.rf"),
+        "include \"a.rf\"\ninclude \"b.rf\"\ninclude \"lib.rf\"\na . b .\n",
+    )
+    .expect("unable to write program");
+    assert_eq!(simulate(&dir, &[], "main.rf", &[], b""), ("4\n9\n".to_owned(), Some(0)));
+
+    let stderr = simulate_error("include_missing", &["--error-format=json"], "include \"missing.rf\"\n");
+    assert!(
+        stderr.contains("\"code\":\"include\",\"message\":\"unable to find included file 'missing.rf'\""),
+        "unexpected diagnostic: {stderr}"
+    );
+}
+
+#[test]
+fn constants() {
+    assert_modes_agree(
+        "constants",
+        "const A 6 end\nconst B A 7 * end\nconst C 1 B shl 1 - end\nB . C . A B + .\n",
+        "42\n4398046511103\n48\n",
+    );
+}
+
+/// Runs `source` in simulation mode with the given options expecting it to be
+/// rejected before it runs, and returns the diagnostics it printed.
+fn simulate_error(name: &str, options: &[&str], source: &str) -> String {
+    let dir = workdir(name);
+    let file = format!("{name}.rf");
+    fs::write(dir.join(&file), source).expect("unable to write program");
+    let output = Command::new(RUSTYFORTH)
+        .current_dir(&dir)
+        .args(options)
+        .args(["sim", &file])
+        .output()
+        .expect("unable to run simulator");
+    assert_eq!(output.status.code(), Some(1), "expected {name} to fail: {output:?}");
+    assert!(output.stdout.is_empty(), "{name} printed to stdout: {output:?}");
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn memory() {
+    assert_modes_agree(
+        "memory",
+        "'a' mem c! 'b' mem 1 + c! '\\n' mem 2 + c!\n3 mem puts\n\
+         -1 mem 8 + !\n\
+         mem 8 + c@ . mem 8 + w@ . mem 8 + l@ . mem 8 + @ .\n\
+         0x11223344 mem 8 + l! mem 8 + @ .\n\
+         0x5566 mem 8 + w! mem 8 + @ .\n\
+         0x77 mem 8 + c! mem 8 + @ . mem 8 + l@ .\n\
+         0x1234 mem 16 + w! mem 16 + c@ . mem 16 + @ .\n\
+         \"xyz\" nip c@ emit '\\n' emit\n",
+        "ab\n255\n65535\n4294967295\n-1\n-4007513276\n-4007504538\n-4007504521\n287462775\n52\n4660\nx\n",
+    );
+    let stderr = simulate_error("memory_out_of_range", &[], "5 mem 639999 + w!\n");
+    assert!(
+        stderr.starts_with("Error: memory_out_of_range.rf:1:16: invalid memory access of 2 bytes at address "),
+        "unexpected diagnostic: {stderr}"
+    );
+    assert!(stderr.contains("1 | 5 mem 639999 + w!\n  |                ^^\n"), "unexpected diagnostic: {stderr}");
+}
+
+#[test]
+fn stack_effects() {
+    assert_modes_agree(
+        "stack_effects",
+        ": fact dup 1 <= if drop 1 else dup 1 - fact * end ;\n\
+         : pick3 rot dup 2swap rot swap ;\n\
+         5 fact . 1 2 3 pick3 . . . .\n",
+        "120\n3\n1\n2\n1\n",
+    );
+    for (name, source, expected) in [
+        ("underflow", "1 2 + . .\n", ":1:9: stack underflow: needs 1 value but the stack has 0"),
+        ("word_underflow", ": add3 + + ;\n1 2 add3\n", ":2:5: stack underflow: needs 3 values but the stack has 2"),
+        ("unbalanced_if", "1 if 2 end\n", ":1:8: 'if' without 'else' must leave the stack depth unchanged"),
+        ("unbalanced_else", "1 if 2 else 3 4 end\n", ":1:17: 'if' and 'else' branches leave different stack depths"),
+        ("unbalanced_while", "0 while 1 do 1 end\n", ":1:16: 'while' loop must leave the stack depth unchanged"),
+    ] {
+        let stderr = simulate_error(name, &[], source);
+        assert!(stderr.contains(expected), "unexpected error for {name}: {stderr}");
+    }
+}
+
+#[test]
+fn typecheck() {
+    let dir = workdir("typecheck");
+    fs::write(
+        dir.join("typecheck.rf"),
+        ": inc 1 + ;\n\
+         : flag 1 2 < ;\n\
+         65 mem inc c! 1 mem inc puts\n\
+         flag if 1 . end 1 cast(bool) if 2 . end mem 8 + mem - .\n\
+         flag 0= if 3 . else 4 . end 5 not .\n",
+    )
+    .expect("unable to write program");
+    let result = simulate(&dir, &["--typecheck"], "typecheck.rf", &[], b"");
+    assert_eq!(result, ("A1\n2\n8\n4\n-6\n".to_owned(), Some(0)));
+
+    for (name, source, expected) in [
+        ("int_condition", "1 if 2 . end\n", ":1:3: type mismatch: expected bool but found int"),
+        ("ptr_plus_ptr", "mem mem + .\n", ":1:9: type mismatch: cannot add ptr and ptr"),
+        ("bool_arithmetic", ": flag 1 2 < ;\nflag 3 + .\n", ":2:8: type mismatch: cannot add bool and int"),
+        ("load_from_int", "5 @ .\n", ":1:3: type mismatch: expected ptr but found int"),
+        (
+            "not_bool",
+            "1 2 < not if 111 . else 222 . end\n",
+            ":1:7: type mismatch: 'not' is bitwise and cannot negate a bool, use '0=' instead",
+        ),
+        (
+            "branch_types",
+            "1 2 = if 1 else 1 2 < end drop\n",
+            ":1:23: 'if' and 'else' branches leave different types on the stack: [int] and [bool]",
+        ),
+    ] {
+        let stderr = simulate_error(name, &["--typecheck"], source);
+        assert!(stderr.contains(expected), "unexpected error for {name}: {stderr}");
+    }
+    // without the flag only stack effects are checked
+    let dir = workdir("untyped");
+    fs::write(dir.join("untyped.rf"), "1 if 2 . end\n").expect("unable to write program");
+    assert_eq!(simulate(&dir, &[], "untyped.rf", &[], b""), ("2\n".to_owned(), Some(0)));
+}
+
+#[test]
+fn diagnostics() {
+    let stderr = simulate_error("diagnostics", &[], "\\ underflow in a macro\nmacro add + end\n1 add .\n");
+    assert_eq!(
+        stderr,
+        "Error: diagnostics.rf:2:11: stack underflow: needs 2 values but the stack has 1\n  \
+         |\n\
+         2 | macro add + end\n  \
+         |           ^\n    \
+         note: diagnostics.rf:3:3: expanded from macro 'add'\n\
+         Error: found 1 error\n"
+    );
+    let stderr = simulate_error("invalid_number", &[], "1 0xZZ .\n");
+    assert!(stderr.ends_with("1 | 1 0xZZ .\n  |   ^^^^\nError: found 1 error\n"), "unexpected diagnostic: {stderr}");
+}
+
+#[test]
+fn comments() {
+    assert_modes_agree("comments", "1 ( a\n b ) 2 + . \\ 4 .\n3 // 5 .\n. ( ) ( x ( y ) 6 .\n", "3\n3\n6\n");
+    // words after a comment keep their place, also when the comment spans
+    // lines, and everything up to the end of the file is an unterminated one
+    let stderr = simulate_error("comment_errors", &[], "( a\n b ) foo\n1 \\ bar\n2 // baz . )\n( open .\n1 .\n");
+    let errors: Vec<&str> = stderr.lines().filter(|line| line.starts_with("Error: ")).collect();
+    assert_eq!(
+        errors,
+        [
+            "Error: comment_errors.rf:5:1: unterminated comment, expected ')'",
+            "Error: comment_errors.rf:2:6: unknown word 'foo'",
+            "Error: found 2 errors",
+        ]
+    );
+}
+
+#[test]
+fn recursive_macro() {
+    // expands itself twice, without the depth limit stopping everything this
+    // would take about 2^100 expansions
+    let stderr = simulate_error("recursive_macro", &[], "macro m m m end\n1 if m end\n");
+    let errors: Vec<&str> = stderr.lines().filter(|line| line.starts_with("Error: ")).collect();
+    assert_eq!(
+        errors,
+        [
+            "Error: recursive_macro.rf:1:9: macro expansion limit of 100 reached while expanding 'm'",
+            "Error: found 1 error",
+        ]
+    );
+    // the outermost expansion, in the user's code, is always noted
+    assert!(stderr.contains("note: ... and 92 more macro expansions\n    note: recursive_macro.rf:2:6: expanded from macro 'm'\n"));
+}
+
+#[test]
+fn macro_used_many_times() {
+    // every use is limited on its own, together they expand to more tokens
+    // than a single use may
+    let source = format!("macro m 1 2 3 4 5 6 7 8 9 10 + + + + + + + + + drop end\n{}", "m\n".repeat(10_001));
+    assert_modes_agree("macro_used_many_times", &source, "");
+}
+
+#[test]
+fn all_errors_reported() {
+    let stderr = simulate_error(
+        "all_errors_reported",
+        &[],
+        "1 0xZZ .\n2 end\nelse\n: foo 1 if 2 ;\nbar\n1 if 2 .\n",
+    );
+    let errors: Vec<&str> = stderr.lines().filter(|line| line.starts_with("Error: ")).collect();
+    assert_eq!(
+        errors,
+        [
+            "Error: all_errors_reported.rf:1:3: invalid number '0xZZ': invalid digit",
+            "Error: all_errors_reported.rf:2:3: 'end' without an open block",
+            "Error: all_errors_reported.rf:3:1: 'else' without matching 'if'",
+            "Error: all_errors_reported.rf:4:9: 'if' is not closed before ';'",
+            "Error: all_errors_reported.rf:5:1: unknown word 'bar'",
+            "Error: all_errors_reported.rf:6:3: 'if' is never closed, expected 'end'",
+            "Error: found 6 errors",
+        ]
+    );
+}
+
+#[test]
+fn json_diagnostics() {
+    let stderr = simulate_error("json_diagnostics", &["--error-format=json"], "1 0xZZ .\n\"a\\q\" bar\n");
+    assert_eq!(
+        stderr,
+        "{\"file\":\"json_diagnostics.rf\",\"row\":1,\"col\":3,\"end_row\":1,\"end_col\":7,\
+         \"severity\":\"error\",\"code\":\"syntax\",\"message\":\"invalid number '0xZZ': invalid digit\",\"notes\":[]}\n\
+         {\"file\":\"json_diagnostics.rf\",\"row\":2,\"col\":1,\"end_row\":2,\"end_col\":6,\
+         \"severity\":\"error\",\"code\":\"syntax\",\"message\":\"unknown escape sequence '\\\\q'\",\"notes\":[]}\n\
+         {\"file\":\"json_diagnostics.rf\",\"row\":2,\"col\":7,\"end_row\":2,\"end_col\":10,\
+         \"severity\":\"error\",\"code\":\"unknown-word\",\"message\":\"unknown word 'bar'\",\"notes\":[]}\n"
+    );
+    let stderr = simulate_error("json_runtime", &["--error-format=json"], "1 0 /\n");
+    assert!(
+        stderr.contains("\"code\":\"runtime\",\"message\":\"division by zero\""),
+        "unexpected diagnostic: {stderr}"
+    );
+}
+
+#[test]
+fn block_validation() {
+    let stderr = simulate_error(
+        "block_validation",
+        &[],
+        "1 while 2 else 3 do end\n1 if 2 else 3 else 4 end\n1 if 2 do 3 end end\n0 while 1 end\n: f 1 if while\n",
+    );
+    let errors: Vec<&str> = stderr.lines().filter(|line| line.starts_with("Error: ")).collect();
+    assert_eq!(
+        errors,
+        [
+            "Error: block_validation.rf:1:11: 'else' without matching 'if'",
+            "Error: block_validation.rf:2:15: 'if' already has an 'else'",
+            "Error: block_validation.rf:3:8: 'do' without matching 'while'",
+            "Error: block_validation.rf:4:11: 'end' closes a 'while' that has no 'do'",
+            "Error: block_validation.rf:5:1: ':' is never closed, expected ';'",
+            "Error: block_validation.rf:5:7: 'if' is never closed, expected 'end'",
+            "Error: block_validation.rf:5:10: 'while' is never followed by 'do'",
+            "Error: found 7 errors",
+        ]
+    );
+}